@@ -0,0 +1,121 @@
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::Command;
+
+use crate::glob;
+
+/// Returns the config files in the directory, in name order.
+fn config_files(dir: &str) -> Result<Vec<PathBuf>, String> {
+    let entries = std::fs::read_dir(dir).map_err(|err| format!("{}: {}", dir, err))?;
+
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| glob::matches("*.toml", name))
+        })
+        .collect();
+
+    files.sort();
+    Ok(files)
+}
+
+/// Options of this run that aren't passed on as they are, `--output-prefix` is combined with the
+/// config name instead.
+const REPLACED_OPTIONS: [&str; 2] = ["--config-dir", "--output-prefix"];
+
+/// Returns the command line arguments of this run without the replaced options, passed on to the
+/// run of every config.
+fn forwarded_args() -> Vec<OsString> {
+    let mut args = Vec::new();
+    let mut skip_value = false;
+
+    for arg in std::env::args_os().skip(1) {
+        if skip_value {
+            skip_value = false;
+            continue;
+        }
+        match arg.to_str() {
+            Some(arg) if REPLACED_OPTIONS.contains(&arg) => skip_value = true,
+            Some(arg)
+                if REPLACED_OPTIONS
+                    .iter()
+                    .any(|option| arg.starts_with(&format!("{}=", option))) => {}
+            _ => args.push(arg),
+        }
+    }
+
+    args
+}
+
+/// Runs every config in the directory as a separate report, each in its own process so that one
+/// bad config doesn't stop the others. Output files are prefixed with the config name after the
+/// output prefix, e.g. `out/team-a-stats.csv` for `team-a.toml` and `out/`. Returns the exit code:
+/// 1 if any run failed, 2 if any gate wasn't met. Progress goes to stderr unless `quiet`, the
+/// summary of the runs to stdout.
+pub fn run_config_dir(dir: &str, output_prefix: &str, quiet: bool) -> i32 {
+    let files = match config_files(dir) {
+        Ok(files) => files,
+        Err(err) => {
+            eprintln!("Failed to read config directory {}", err);
+            return 1;
+        }
+    };
+    if files.is_empty() {
+        eprintln!("No config files found in {}", dir);
+        return 1;
+    }
+
+    let exe = std::env::current_exe().expect("Failed to find current executable");
+    let args = forwarded_args();
+    let mut results = Vec::new();
+
+    for file in &files {
+        let name = file
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default();
+        if !quiet {
+            eprintln!("Running report for {}.", file.display());
+        }
+
+        let status = Command::new(&exe)
+            .args(&args)
+            .arg("--config")
+            .arg(file)
+            .arg("--output-prefix")
+            .arg(format!("{}{}-", output_prefix, name))
+            .status();
+
+        results.push((file, status.ok().and_then(|status| status.code())));
+    }
+
+    if !quiet {
+        eprintln!();
+    }
+    for (file, code) in &results {
+        let result = match code {
+            Some(0) => "ok".to_string(),
+            Some(2) => "gates not met".to_string(),
+            Some(code) => format!("failed with exit code {}", code),
+            None => "failed".to_string(),
+        };
+        println!("{}: {}", file.display(), result);
+    }
+
+    let succeeded = results.iter().filter(|(_, code)| *code == Some(0)).count();
+    println!("{} of {} reports succeeded.", succeeded, results.len());
+
+    if results
+        .iter()
+        .any(|(_, code)| *code != Some(0) && *code != Some(2))
+    {
+        1
+    } else if succeeded < results.len() {
+        2
+    } else {
+        0
+    }
+}
@@ -0,0 +1,67 @@
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::date::Timestamp;
+use crate::review::Review;
+use crate::{output, Config};
+
+const DAY: i64 = 24 * 60 * 60;
+/// Days the first or last change may be inside the requested dates before it's pointed out.
+const SLACK_DAYS: i64 = 30;
+
+fn utc_date(timestamp: i64) -> NaiveDate {
+    NaiveDateTime::from_timestamp(timestamp, 0).date()
+}
+
+/// Prints the dates of every reported user's first and last fetched change next to their requested
+/// dates on stderr. Changes starting or ending more than `SLACK_DAYS` inside the requested dates are
+/// pointed out, as it can mean the fetched data is truncated, e.g. by a query limit, rather than the
+/// user being inactive.
+pub fn print(reviews: &[Review], config: &Config) {
+    let shown = output::shown_usernames(config);
+    for (username, (from, to)) in config.user_dates() {
+        if !config.is_reported(&username) {
+            continue;
+        }
+
+        let times: Vec<i64> = reviews
+            .iter()
+            .filter(|review| review.owner.username == username)
+            .filter_map(|review| review.counted_time(&config.submit_label))
+            .collect();
+        let (first, last) = match (times.iter().min(), times.iter().max()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => {
+                eprintln!(
+                    "Coverage of {}: no changes fetched, requested {} to {}",
+                    shown[&username], from, to
+                );
+                continue;
+            }
+        };
+
+        let mut notes = Vec::new();
+        let late = (first - from.timestamp("00:00:00")) / DAY;
+        if late > SLACK_DAYS {
+            notes.push(format!("first change {} days after the start", late));
+        }
+        let early = (to.timestamp("23:59:59") - last) / DAY;
+        if early > SLACK_DAYS {
+            notes.push(format!("last change {} days before the end", early));
+        }
+        let notes = if notes.is_empty() {
+            String::new()
+        } else {
+            format!(", {}", notes.join(", "))
+        };
+
+        eprintln!(
+            "Coverage of {}: changes from {} to {}, requested {} to {}{}",
+            shown[&username],
+            utc_date(first),
+            utc_date(last),
+            from,
+            to,
+            notes
+        );
+    }
+}
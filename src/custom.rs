@@ -0,0 +1,115 @@
+use serde::Deserialize;
+use std::fmt;
+
+use crate::review::Review;
+
+/// A metric computed for every counted change and summed into the owner's rows. Every registered
+/// metric gets a column and an exported metric named after it, so it can also be used in gates.
+pub struct CustomMetric {
+    pub name: String,
+    pub value: Box<dyn Fn(&Review) -> u32>,
+}
+
+impl CustomMetric {
+    pub fn new<F: Fn(&Review) -> u32 + 'static>(name: &str, value: F) -> Self {
+        Self {
+            name: name.to_string(),
+            value: Box::new(value),
+        }
+    }
+}
+
+impl fmt::Debug for CustomMetric {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CustomMetric({})", self.name)
+    }
+}
+
+/// Value of a change that metrics of the config file are computed from.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChangeValue {
+    PatchSets,
+    SubstantivePatchSets,
+    /// Lines inserted and deleted by the last patch set, changes without sizes have none.
+    Lines,
+    CommitWords,
+}
+
+impl ChangeValue {
+    fn of(self, review: &Review) -> Option<u32> {
+        match self {
+            ChangeValue::PatchSets => Some(review.patch_set_count()),
+            ChangeValue::SubstantivePatchSets => Some(review.substantive_patch_set_count()),
+            ChangeValue::Lines => review.lines_changed(),
+            ChangeValue::CommitWords => Some(review.commit_message_words()),
+        }
+    }
+}
+
+/// Metric defined in the config file, e.g. the number of big changes. With `min` or `max` it
+/// counts the changes whose value is within them, without either it sums the value.
+#[derive(Debug, Deserialize)]
+pub struct MetricDefinition {
+    pub name: String,
+    pub value: ChangeValue,
+    pub min: Option<u32>,
+    pub max: Option<u32>,
+}
+
+impl MetricDefinition {
+    /// Checks that the name can be used as a column and an exported metric, e.g. `big_changes`.
+    pub fn validate(&self) -> Result<(), String> {
+        let valid = |c: char| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_';
+        if self.name.is_empty() || !self.name.chars().all(valid) {
+            return Err(format!(
+                "invalid metric name '{}', expected lowercase letters, digits and _",
+                self.name
+            ));
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return Err(format!("metric '{}' has min above max", self.name));
+            }
+        }
+        Ok(())
+    }
+
+    fn metric(&self) -> CustomMetric {
+        let (value, min, max) = (self.value, self.min, self.max);
+        if min.is_none() && max.is_none() {
+            return CustomMetric::new(&self.name, move |review| {
+                value.of(review).unwrap_or_default()
+            });
+        }
+        CustomMetric::new(&self.name, move |review| {
+            let within = value.of(review).is_some_and(|value| {
+                min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+            });
+            u32::from(within)
+        })
+    }
+}
+
+/// Adds a metric to collect next to the built-in ones, failing if one of the name is already
+/// registered.
+fn register(metrics: &mut Vec<CustomMetric>, metric: CustomMetric) -> Result<(), String> {
+    if metrics.iter().any(|existing| existing.name == metric.name) {
+        return Err(format!(
+            "metric '{}' is defined more than once",
+            metric.name
+        ));
+    }
+    metrics.push(metric);
+    Ok(())
+}
+
+/// Returns the metrics defined in the config file, each name can only be defined once.
+pub fn registered(definitions: &[MetricDefinition]) -> Result<Vec<CustomMetric>, String> {
+    let mut metrics = Vec::new();
+    for definition in definitions {
+        definition.validate()?;
+        register(&mut metrics, definition.metric())?;
+    }
+    Ok(metrics)
+}
@@ -0,0 +1,319 @@
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use serde::{de, Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+use toml::value::Datetime;
+
+/// Date from the config file with an optional UTC offset, e.g. `2021-01-01` or `"2021-01-01 +02:00"`.
+/// Dates without an offset are in UTC.
+#[derive(Debug, Clone)]
+pub struct Date {
+    date: NaiveDate,
+    offset: i64,
+}
+
+pub trait Timestamp {
+    fn timestamp(&self, time: &str) -> i64;
+}
+
+/// Returns the UTC timestamp of the given local time on that date.
+impl Timestamp for Date {
+    fn timestamp(&self, time: &str) -> i64 {
+        let datetime = format!("{}T{}", self.date.format("%Y-%m-%d"), time);
+        NaiveDateTime::parse_from_str(&datetime, "%Y-%m-%dT%H:%M:%S")
+            .expect("Failed to parse datetime")
+            .timestamp()
+            - self.offset
+    }
+}
+
+/// Returns whether the timestamp falls between the start of `from` and the end of `to`.
+pub fn is_within(from: &Date, to: &Date, timestamp: i64) -> bool {
+    from.timestamp("00:00:00") <= timestamp && timestamp <= to.timestamp("23:59:59")
+}
+
+impl Date {
+    /// Returns the UTC date of the given local time, used for building Gerrit queries.
+    pub fn utc_date(&self, time: &str) -> NaiveDate {
+        NaiveDateTime::from_timestamp(self.timestamp(time), 0).date()
+    }
+}
+
+fn parse_offset(offset: &str) -> Result<i64, String> {
+    if offset == "Z" || offset == "UTC" {
+        return Ok(0);
+    }
+
+    let invalid = || format!("invalid UTC offset '{}', expected e.g. +02:00", offset);
+    let sign = match offset.chars().next() {
+        Some('+') => 1,
+        Some('-') => -1,
+        _ => return Err(invalid()),
+    };
+
+    let mut parts = offset[1..].splitn(2, ':');
+    let hours: i64 = parts
+        .next()
+        .and_then(|hours| hours.parse().ok())
+        .ok_or_else(invalid)?;
+    let minutes: i64 = parts
+        .next()
+        .and_then(|minutes| minutes.parse().ok())
+        .ok_or_else(invalid)?;
+
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+
+    Ok(sign * (hours * 3600 + minutes * 60))
+}
+
+impl FromStr for Date {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut parts = value.split_whitespace();
+        let date = parts.next().unwrap_or_default();
+        let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .map_err(|_| format!("invalid date '{}', expected e.g. 2021-01-01", value))?;
+        let offset = match parts.next() {
+            Some(offset) => parse_offset(offset)?,
+            None => 0,
+        };
+
+        if parts.next().is_some() {
+            return Err(format!("unexpected text after date '{}'", value));
+        }
+
+        Ok(Self { date, offset })
+    }
+}
+
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.date.format("%Y-%m-%d"))?;
+        if self.offset != 0 {
+            let sign = if self.offset < 0 { '-' } else { '+' };
+            let offset = self.offset.abs();
+            write!(f, " {}{:02}:{:02}", sign, offset / 3600, offset % 3600 / 60)?;
+        }
+        Ok(())
+    }
+}
+
+/// Accepts both TOML dates and strings, so that offsets can be written as `"2021-01-01 +02:00"`.
+impl<'de> Deserialize<'de> for Date {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawDate {
+            Datetime(Datetime),
+            String(String),
+        }
+
+        let value = match RawDate::deserialize(deserializer)? {
+            RawDate::Datetime(datetime) => datetime.to_string(),
+            RawDate::String(string) => string,
+        };
+
+        value.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Calendar period used for grouping changes by their submit time.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bucket {
+    Week,
+    Month,
+    Quarter,
+    Year,
+}
+
+impl Bucket {
+    pub const NAMES: &'static [&'static str] = &["week", "month", "quarter", "year"];
+
+    /// Returns the label of the period containing the UTC timestamp, e.g. `2019-W05`, `2019-03`,
+    /// `2019-Q1` or `2019`. Labels sort chronologically.
+    pub fn period(self, timestamp: i64) -> String {
+        let date = NaiveDateTime::from_timestamp(timestamp, 0).date();
+
+        match self {
+            Bucket::Week => {
+                let week = date.iso_week();
+                format!("{}-W{:02}", week.year(), week.week())
+            }
+            Bucket::Month => format!("{}-{:02}", date.year(), date.month()),
+            Bucket::Quarter => format!("{}-Q{}", date.year(), (date.month() - 1) / 3 + 1),
+            Bucket::Year => date.year().to_string(),
+        }
+    }
+}
+
+impl FromStr for Bucket {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "week" => Ok(Bucket::Week),
+            "month" => Ok(Bucket::Month),
+            "quarter" => Ok(Bucket::Quarter),
+            "year" => Ok(Bucket::Year),
+            _ => Err(format!("invalid bucket '{}'", value)),
+        }
+    }
+}
+
+/// Labels of the buckets that delays are grouped in, see `delay_bucket`.
+pub const DELAY_BUCKETS: &[&str] = &["<1h", "<1d", "<1w", ">1w"];
+
+/// Returns the index of the bucket in `DELAY_BUCKETS` that the delay in seconds falls in.
+pub fn delay_bucket(seconds: i64) -> usize {
+    const HOUR: i64 = 60 * 60;
+
+    match seconds {
+        seconds if seconds < HOUR => 0,
+        seconds if seconds < 24 * HOUR => 1,
+        seconds if seconds < 7 * 24 * HOUR => 2,
+        _ => 3,
+    }
+}
+
+/// Formats a duration in seconds with its two largest units, e.g. `2d 4h` or `35m`. Durations
+/// under a second are shown in milliseconds.
+pub fn format_duration(seconds: f64) -> String {
+    const UNITS: [(&str, i64); 4] = [("d", 24 * 60 * 60), ("h", 60 * 60), ("m", 60), ("s", 1)];
+
+    if seconds < 1.0 {
+        return format!("{}ms", (seconds * 1000.0).round());
+    }
+
+    let seconds = seconds.round() as i64;
+    let largest = UNITS
+        .iter()
+        .position(|(_, unit)| seconds >= *unit)
+        .unwrap_or(UNITS.len() - 1);
+
+    let mut parts = Vec::new();
+    for (index, (name, unit)) in UNITS.iter().enumerate().skip(largest).take(2) {
+        let value = match index {
+            0 => seconds / unit,
+            _ => seconds % UNITS[index - 1].1 / unit,
+        };
+        if index == largest || value > 0 {
+            parts.push(format!("{}{}", value, name));
+        }
+    }
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JAN_1: i64 = 1_546_300_800;
+    const JAN_31_END: i64 = 1_548_979_199;
+
+    fn date(value: &str) -> Date {
+        value.parse().expect("Failed to parse date")
+    }
+
+    #[test]
+    fn timestamp_of_day_boundaries() {
+        assert_eq!(date("2019-01-01").timestamp("00:00:00"), JAN_1);
+        assert_eq!(date("2019-01-31").timestamp("23:59:59"), JAN_31_END);
+    }
+
+    #[test]
+    fn timestamp_of_toml_datetime() {
+        #[derive(Deserialize)]
+        struct Dates {
+            from: Date,
+            to: Date,
+        }
+
+        let dates: Dates =
+            toml::from_str("from = 2019-01-01\nto = 2019-01-31").expect("Failed to parse dates");
+        assert_eq!(dates.from.timestamp("00:00:00"), JAN_1);
+        assert_eq!(dates.to.timestamp("23:59:59"), JAN_31_END);
+    }
+
+    #[test]
+    fn timestamp_with_offset() {
+        assert_eq!(
+            date("2019-01-01 +02:00").timestamp("00:00:00"),
+            JAN_1 - 2 * 3600
+        );
+        assert_eq!(
+            date("2019-01-01 -05:30").timestamp("00:00:00"),
+            JAN_1 + 5 * 3600 + 1800
+        );
+        assert_eq!(date("2019-01-01 Z").timestamp("00:00:00"), JAN_1);
+    }
+
+    #[test]
+    fn is_within_includes_boundary_seconds() {
+        let (from, to) = (date("2019-01-01"), date("2019-01-31"));
+
+        assert!(is_within(&from, &to, JAN_1));
+        assert!(is_within(&from, &to, JAN_31_END));
+        assert!(!is_within(&from, &to, JAN_1 - 1));
+        assert!(!is_within(&from, &to, JAN_31_END + 1));
+    }
+
+    #[test]
+    fn is_within_single_day() {
+        let day = date("2019-01-01");
+
+        assert!(is_within(&day, &day, JAN_1));
+        assert!(is_within(&day, &day, JAN_1 + 24 * 3600 - 1));
+        assert!(!is_within(&day, &day, JAN_1 + 24 * 3600));
+    }
+
+    #[test]
+    fn is_within_shifts_with_offset() {
+        let (from, to) = (date("2019-01-01 +02:00"), date("2019-01-31 +02:00"));
+
+        assert!(is_within(&from, &to, JAN_1 - 2 * 3600));
+        assert!(!is_within(&from, &to, JAN_1 - 2 * 3600 - 1));
+        assert!(is_within(&from, &to, JAN_31_END - 2 * 3600));
+        assert!(!is_within(&from, &to, JAN_31_END - 2 * 3600 + 1));
+    }
+
+    #[test]
+    fn format_duration_with_two_largest_units() {
+        assert_eq!(
+            format_duration(2.0 * 86400.0 + 4.0 * 3600.0 + 59.0),
+            "2d 4h"
+        );
+        assert_eq!(format_duration(35.0 * 60.0), "35m");
+        assert_eq!(format_duration(3600.0 + 30.0), "1h");
+        assert_eq!(format_duration(90.4), "1m 30s");
+        assert_eq!(format_duration(1.0), "1s");
+    }
+
+    #[test]
+    fn format_duration_below_second() {
+        assert_eq!(format_duration(0.0123), "12ms");
+        assert_eq!(format_duration(0.0), "0ms");
+    }
+
+    #[test]
+    fn bucket_period_labels() {
+        const MAR_31_END: i64 = 1_554_076_799;
+
+        assert_eq!(Bucket::Week.period(JAN_1), "2019-W01");
+        assert_eq!(Bucket::Month.period(JAN_31_END), "2019-01");
+        assert_eq!(Bucket::Month.period(JAN_31_END + 1), "2019-02");
+        assert_eq!(Bucket::Quarter.period(MAR_31_END), "2019-Q1");
+        assert_eq!(Bucket::Quarter.period(MAR_31_END + 1), "2019-Q2");
+        assert_eq!(Bucket::Year.period(JAN_1), "2019");
+    }
+
+    #[test]
+    fn bucket_week_uses_iso_year() {
+        // 2019-12-30 is in the first week of 2020, 2021-01-03 in the last week of 2020.
+        assert_eq!(Bucket::Week.period(1_577_664_000), "2020-W01");
+        assert_eq!(Bucket::Week.period(1_609_632_000), "2020-W53");
+        assert_eq!(Bucket::Year.period(1_577_664_000), "2019");
+    }
+}
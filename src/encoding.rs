@@ -0,0 +1,109 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Characters Windows-1252 places in 0x80-0x9F, where ISO-8859-1 has control codes.
+const WINDOWS_1252_HIGH: [Option<char>; 32] = [
+    Some('\u{20AC}'),
+    None,
+    Some('\u{201A}'),
+    Some('\u{0192}'),
+    Some('\u{201E}'),
+    Some('\u{2026}'),
+    Some('\u{2020}'),
+    Some('\u{2021}'),
+    Some('\u{02C6}'),
+    Some('\u{2030}'),
+    Some('\u{0160}'),
+    Some('\u{2039}'),
+    Some('\u{0152}'),
+    None,
+    Some('\u{017D}'),
+    None,
+    None,
+    Some('\u{2018}'),
+    Some('\u{2019}'),
+    Some('\u{201C}'),
+    Some('\u{201D}'),
+    Some('\u{2022}'),
+    Some('\u{2013}'),
+    Some('\u{2014}'),
+    Some('\u{02DC}'),
+    Some('\u{2122}'),
+    Some('\u{0161}'),
+    Some('\u{203A}'),
+    Some('\u{0153}'),
+    None,
+    Some('\u{017E}'),
+    Some('\u{0178}'),
+];
+
+/// Character encoding the output files are written in.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Windows1252,
+    Latin1,
+}
+
+impl Encoding {
+    pub const NAMES: &'static [&'static str] = &["utf-8", "windows-1252", "iso-8859-1"];
+
+    fn encode_char(self, c: char) -> Option<u8> {
+        let code = c as u32;
+        match self {
+            Encoding::Utf8 => None,
+            Encoding::Latin1 if code <= 0xFF => Some(code as u8),
+            Encoding::Windows1252 if code < 0x80 || (0xA0..=0xFF).contains(&code) => {
+                Some(code as u8)
+            }
+            Encoding::Windows1252 => WINDOWS_1252_HIGH
+                .iter()
+                .position(|high| *high == Some(c))
+                .map(|index| 0x80 + index as u8),
+            Encoding::Latin1 => None,
+        }
+    }
+
+    /// Encodes the text, returning the first character the encoding can't represent as the error.
+    /// With `lossy` such characters are replaced by '?' instead.
+    pub fn encode(self, text: &str, lossy: bool) -> Result<Vec<u8>, char> {
+        if self == Encoding::Utf8 {
+            return Ok(text.as_bytes().to_vec());
+        }
+
+        let mut bytes = Vec::with_capacity(text.len());
+        for c in text.chars() {
+            match self.encode_char(c) {
+                Some(byte) => bytes.push(byte),
+                None if lossy => bytes.push(b'?'),
+                None => return Err(c),
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Windows1252 => "windows-1252",
+            Encoding::Latin1 => "iso-8859-1",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "utf-8" => Ok(Encoding::Utf8),
+            "windows-1252" => Ok(Encoding::Windows1252),
+            "iso-8859-1" => Ok(Encoding::Latin1),
+            _ => Err(format!("invalid encoding '{}'", value)),
+        }
+    }
+}
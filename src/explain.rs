@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+use crate::review::Review;
+use crate::{date, output, Config};
+
+/// Returns the votes the user gave on the last patch set, e.g. `Code-Review+2`.
+fn votes(review: &Review, username: &str, config: &Config) -> Vec<String> {
+    let approvals = review
+        .patch_sets
+        .last()
+        .and_then(|patch| patch.approvals.as_ref());
+
+    approvals
+        .into_iter()
+        .flatten()
+        .filter(|approval| approval.by.username == username)
+        .filter(|approval| approval.review_type != config.submit_label)
+        .map(|approval| {
+            let sign = if approval.value.starts_with('-') {
+                ""
+            } else {
+                "+"
+            };
+            format!("{}{}{}", approval.review_type, sign, approval.value)
+        })
+        .collect()
+}
+
+/// Formats a latency in readable units like `2d 4h`, or in seconds with `--raw-durations`.
+fn duration(seconds: i64, config: &Config) -> String {
+    let seconds = seconds.max(0);
+    if config.raw_durations {
+        format!("{}s", seconds)
+    } else {
+        date::format_duration(seconds as f64)
+    }
+}
+
+/// Formats the latencies as a list, e.g. `[35m, 2h 10m]`.
+fn durations(latencies: &[i64], config: &Config) -> String {
+    let shown: Vec<String> = latencies
+        .iter()
+        .map(|&latency| duration(latency, config))
+        .collect();
+    format!("[{}]", shown.join(", "))
+}
+
+/// Writes every counted change the user authored and every change they reviewed, with the numbers
+/// that went into their statistics. Like the CSV files it's written to a temporary file first and
+/// shows pseudonyms when anonymizing.
+pub fn write_explanation(filepath: &str, reviews: &[Review], username: &str, config: &Config) {
+    let temp_path = format!("{}.{}.tmp", filepath, std::process::id());
+    let file = File::create(&temp_path).expect("Failed to create explain file");
+    let mut writer = BufWriter::new(file);
+    let users = config.user_names();
+    let dates = config.user_dates();
+    let shown = output::shown_usernames(config);
+
+    let counted: Vec<&Review> = reviews
+        .iter()
+        .filter(|review| config.is_counted_review(review))
+        .filter(|review| {
+            let (from, to) = &dates[&review.owner.username];
+            review.is_within_date(from, to, &config.submit_label)
+        })
+        .collect();
+
+    writeln!(writer, "Changes authored by {}:", shown[username])
+        .expect("Failed to write explain file");
+    for review in counted
+        .iter()
+        .filter(|review| review.owner.username == username)
+    {
+        let approvers: Vec<String> = review
+            .approvals(
+                &users,
+                &config.approval_labels,
+                &config.repo_approval_min,
+                config.approval_patch_sets,
+            )
+            .into_iter()
+            .map(|(user, _, _)| shown[&user].clone())
+            .collect();
+        let first_response = match review.first_response(&users, &config.submit_label) {
+            Some((user, latency)) => format!("{} by {}", duration(latency, config), shown[&user]),
+            None => "none".to_string(),
+        };
+        let open = match review.open_interval(&config.submit_label) {
+            Some((start, end)) => duration(end - start, config),
+            None => "unknown".to_string(),
+        };
+        writeln!(
+            writer,
+            "  {} {}: comments received {}, patch sets {}, approvers [{}], first response {}, \
+             author turnarounds {}, open {}",
+            review.number,
+            review.repository_name(),
+            review.comments_received(&config.ci_accounts),
+            review.patch_set_count(),
+            approvers.join(", "),
+            first_response,
+            durations(&review.author_turnarounds(&config.ci_accounts), config),
+            open
+        )
+        .expect("Failed to write explain file");
+    }
+
+    writeln!(writer, "\nReviews given by {}:", shown[username])
+        .expect("Failed to write explain file");
+    for review in counted
+        .iter()
+        .filter(|review| review.owner.username != username)
+    {
+        let comments = review
+            .comments_made(&users)
+            .get(username)
+            .copied()
+            .unwrap_or_default();
+        let votes = votes(review, username, config);
+        if comments == 0 && votes.is_empty() {
+            continue;
+        }
+        let first_response = match review.first_response(&users, &config.submit_label) {
+            Some((user, latency)) if user == username => duration(latency, config),
+            _ => "none".to_string(),
+        };
+        let delays: Vec<i64> = review
+            .approval_delays(&users, &config.approval_labels, &config.repo_approval_min)
+            .into_iter()
+            .filter(|(user, _)| user == username)
+            .map(|(_, delay)| delay)
+            .collect();
+        writeln!(
+            writer,
+            "  {} {}: comments made {}, votes [{}], first response {}, approval delays {}",
+            review.number,
+            review.repository_name(),
+            comments,
+            votes.join(", "),
+            first_response,
+            durations(&delays, config)
+        )
+        .expect("Failed to write explain file");
+    }
+
+    let file = writer.into_inner().expect("Failed to flush explain file");
+    file.sync_all().expect("Failed to sync explain file");
+    std::fs::rename(&temp_path, filepath).expect("Failed to move explain file into place");
+}
@@ -0,0 +1,187 @@
+use std::fmt;
+
+use crate::{get_total_stats, output, Config, PeriodStatistics};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Op {
+    fn holds(self, actual: f64, expected: f64) -> bool {
+        match self {
+            Op::Less => actual < expected,
+            Op::LessOrEqual => actual <= expected,
+            Op::Greater => actual > expected,
+            Op::GreaterOrEqual => actual >= expected,
+        }
+    }
+}
+
+/// Threshold the collected metrics have to meet, parsed from `[each:]<metric><op><value>`, where
+/// `op` is one of `=`, `>=`, `>`, `<=` and `<`. Plain `=` is a minimum, same as `>=`. Gates are
+/// checked against team totals, or against every reported user when prefixed with `each:`.
+#[derive(Debug)]
+pub struct Gate {
+    per_user: bool,
+    metric: String,
+    op: Op,
+    value: f64,
+}
+
+impl fmt::Display for Gate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self.op {
+            Op::Less => "<",
+            Op::LessOrEqual => "<=",
+            Op::Greater => ">",
+            Op::GreaterOrEqual => ">=",
+        };
+        let prefix = if self.per_user { "each:" } else { "" };
+        write!(f, "{}{}{}{}", prefix, self.metric, op, self.value)
+    }
+}
+
+pub fn parse_gate(gate: &str, config: &Config) -> Result<Gate, String> {
+    let (per_user, expression) = match gate.strip_prefix("each:") {
+        Some(expression) => (true, expression),
+        None => (false, gate),
+    };
+
+    let index = expression
+        .find(['<', '>', '='])
+        .ok_or_else(|| format!("invalid gate '{}', expected e.g. comments_made=50", gate))?;
+    let (metric, rest) = expression.split_at(index);
+    let (op, value) = if let Some(value) = rest.strip_prefix(">=") {
+        (Op::GreaterOrEqual, value)
+    } else if let Some(value) = rest.strip_prefix("<=") {
+        (Op::LessOrEqual, value)
+    } else if let Some(value) = rest.strip_prefix('>') {
+        (Op::Greater, value)
+    } else if let Some(value) = rest.strip_prefix('<') {
+        (Op::Less, value)
+    } else {
+        (Op::GreaterOrEqual, rest.trim_start_matches('='))
+    };
+
+    let metric = metric.trim();
+    if !output::metric_names(config)
+        .iter()
+        .any(|name| name == metric)
+    {
+        return Err(format!(
+            "unknown metric '{}' in gate '{}', expected one of: {}",
+            metric,
+            gate,
+            output::metric_names(config).join(",")
+        ));
+    }
+
+    let value = value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid value in gate '{}'", gate))?;
+
+    Ok(Gate {
+        per_user,
+        metric: metric.to_string(),
+        op,
+        value,
+    })
+}
+
+fn metric_value(stats: &crate::Stats, metric: &str, config: &Config) -> f64 {
+    output::metrics(stats, config)
+        .into_iter()
+        .find(|(name, _)| *name == metric)
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0.0)
+}
+
+/// Checks all gates in every period, returns descriptions of the ones that failed.
+pub fn check_gates(stats: &PeriodStatistics, config: &Config) -> Vec<String> {
+    let mut failures = Vec::new();
+    let shown = output::shown_usernames(config);
+
+    for (period, stats) in stats {
+        let period = match config.bucket {
+            Some(_) => format!(" in {}", period),
+            None => String::new(),
+        };
+
+        for gate in &config.gates {
+            if gate.per_user {
+                for (user, repos) in stats.iter().filter(|(user, _)| config.is_reported(user)) {
+                    let repo = repos.get("All").expect("Failed to get 'All' row");
+                    let actual = metric_value(repo, &gate.metric, config);
+                    if !gate.op.holds(actual, gate.value) {
+                        let user = &shown[user];
+                        failures.push(format!("{} for {}{}: {}", gate, user, period, actual));
+                    }
+                }
+            } else {
+                let total = get_total_stats(stats, "All");
+                let actual = metric_value(&total, &gate.metric, config);
+                if !gate.op.holds(actual, gate.value) {
+                    failures.push(format!("{} for team{}: {}", gate, period, actual));
+                }
+            }
+        }
+    }
+
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        let config = "server = \"gerrit\"\nport = \"29418\"\nfrom = 2019-01-01\nto = 2019-12-31\nuser = []\n";
+        toml::from_str(config).expect("Failed to parse config")
+    }
+
+    #[test]
+    fn parse_gate_operators() {
+        let config = config();
+        let parsed = |gate: &str| {
+            let gate = parse_gate(gate, &config).expect("Failed to parse gate");
+            (gate.per_user, gate.metric, gate.op, gate.value)
+        };
+
+        assert_eq!(
+            parsed("comments_made=50"),
+            (false, "comments_made".to_string(), Op::GreaterOrEqual, 50.0)
+        );
+        assert_eq!(
+            parsed("each:changes >= 2.5"),
+            (true, "changes".to_string(), Op::GreaterOrEqual, 2.5)
+        );
+        assert_eq!(parsed("changes>3").2, Op::Greater);
+        assert_eq!(parsed("changes<=3").2, Op::LessOrEqual);
+        assert_eq!(parsed("changes<3").2, Op::Less);
+        assert_eq!(
+            parse_gate("each:changes<3", &config).unwrap().to_string(),
+            "each:changes<3"
+        );
+    }
+
+    #[test]
+    fn parse_malformed_gate() {
+        let config = config();
+        for gate in [
+            "",
+            "changes",
+            "each:",
+            "changes=",
+            "changes=lots",
+            "changes=>3",
+            "nonsense=3",
+        ] {
+            assert!(parse_gate(gate, &config).is_err(), "{} parsed", gate);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::{Config, PeriodStatistics};
+
+/// Returns the Gini coefficient of the values, from 0 when they are all equal to almost 1 when a
+/// single one holds everything. It's computed from the sorted values as
+/// `2 * sum(i * x_i) / (n * sum(x)) - (n + 1) / n` with `i` counting from 1. Without any values or
+/// when they are all 0 there's nothing to compare and it's `None`.
+pub fn gini(values: &[u32]) -> Option<f64> {
+    let mut values: Vec<f64> = values.iter().map(|value| f64::from(*value)).collect();
+    values.sort_by(|a, b| a.partial_cmp(b).expect("Failed to compare values"));
+
+    let n = values.len() as f64;
+    let total: f64 = values.iter().sum();
+    if total == 0.0 {
+        return None;
+    }
+
+    let weighted: f64 = values
+        .iter()
+        .enumerate()
+        .map(|(i, value)| (i + 1) as f64 * value)
+        .sum();
+    Some(2.0 * weighted / (n * total) - (n + 1.0) / n)
+}
+
+/// Returns the Gini coefficients of the changes authored and of the reviews given, comments made
+/// plus approvals, by every configured user in each period. Users without activity count as 0.
+pub fn team_gini(
+    stats: &PeriodStatistics,
+    config: &Config,
+) -> BTreeMap<String, (Option<f64>, Option<f64>)> {
+    let users = config.user_names();
+
+    stats
+        .iter()
+        .map(|(period, stats)| {
+            let (changes, reviews): (Vec<u32>, Vec<u32>) = users
+                .keys()
+                .map(|user| {
+                    stats
+                        .get(user)
+                        .and_then(|repos| repos.get("All"))
+                        .map_or((0, 0), |all| {
+                            (all.changes, all.comments_made + all.approvals)
+                        })
+                })
+                .unzip();
+            (period.clone(), (gini(&changes), gini(&reviews)))
+        })
+        .collect()
+}
+
+fn format(value: Option<f64>) -> String {
+    value.map_or_else(|| "n/a".to_string(), |value| format!("{:.3}", value))
+}
+
+/// Prints the Gini coefficients of every period.
+pub fn print(stats: &PeriodStatistics, config: &Config) {
+    for (period, (changes, reviews)) in team_gini(stats, config) {
+        let period = match config.bucket {
+            Some(_) => format!(" in {}", period),
+            None => String::new(),
+        };
+        println!(
+            "Gini{}: changes authored {}, reviews given {}",
+            period,
+            format(changes),
+            format(reviews)
+        );
+    }
+}
+
+/// Returns the Gini coefficients by period for the stats line.
+pub fn to_json(stats: &PeriodStatistics, config: &Config) -> Value {
+    let periods: serde_json::Map<String, Value> = team_gini(stats, config)
+        .into_iter()
+        .map(|(period, (changes, reviews))| {
+            (period, json!({ "changes": changes, "reviews": reviews }))
+        })
+        .collect();
+    Value::Object(periods)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("Failed to compute coefficient");
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn gini_of_equal_values_is_zero() {
+        assert_close(gini(&[5, 5, 5, 5]), 0.0);
+    }
+
+    #[test]
+    fn gini_of_single_holder() {
+        // One of n users holding everything gives (n - 1) / n.
+        assert_close(gini(&[0, 0, 0, 12]), 0.75);
+        assert_close(gini(&[7]), 0.0);
+    }
+
+    #[test]
+    fn gini_ignores_order() {
+        assert_close(gini(&[3, 1, 2]), gini(&[1, 2, 3]).unwrap());
+        assert_close(gini(&[1, 2, 3]), 2.0 / 9.0);
+    }
+
+    #[test]
+    fn gini_without_activity() {
+        assert_eq!(gini(&[]), None);
+        assert_eq!(gini(&[0, 0]), None);
+    }
+}
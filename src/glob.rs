@@ -0,0 +1,75 @@
+use std::collections::BTreeMap;
+
+/// Matches a name against a glob pattern. `*` matches any characters except `/`, `**` matches any
+/// characters including `/` and `?` matches a single character. Other characters match exactly.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    fn matches_from(pattern: &[char], name: &[char]) -> bool {
+        match pattern.first() {
+            None => name.is_empty(),
+            Some('*') if pattern.get(1) == Some(&'*') => {
+                (0..=name.len()).any(|skip| matches_from(&pattern[2..], &name[skip..]))
+            }
+            Some('*') => {
+                let segment = name.iter().position(|&c| c == '/').unwrap_or(name.len());
+                (0..=segment).any(|skip| matches_from(&pattern[1..], &name[skip..]))
+            }
+            Some('?') => {
+                !name.is_empty() && name[0] != '/' && matches_from(&pattern[1..], &name[1..])
+            }
+            Some(&c) => name.first() == Some(&c) && matches_from(&pattern[1..], &name[1..]),
+        }
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_from(&pattern, &name)
+}
+
+/// Returns whether the name matches any of the patterns.
+pub fn matches_any(patterns: &[String], name: &str) -> bool {
+    patterns.iter().any(|pattern| matches(pattern, name))
+}
+
+/// Returns the value of the first key in the map that matches the name as a glob pattern, e.g. for
+/// per-repo options like `repo_approval_min`. A key equal to the name wins over patterns.
+pub fn lookup<'a, V>(map: &'a BTreeMap<String, V>, name: &str) -> Option<&'a V> {
+    map.get(name).or_else(|| {
+        map.iter()
+            .find(|(pattern, _)| matches(pattern, name))
+            .map(|(_, value)| value)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_matches_within_segment() {
+        assert!(matches("platform/*", "platform/core"));
+        assert!(matches("platform/*", "platform/"));
+        assert!(!matches("platform/*", "platform/core/tests"));
+        assert!(matches("**PROJ-**", "Fix login\n\nPROJ-123"));
+    }
+
+    #[test]
+    fn question_mark_matches_single_character() {
+        assert!(matches("release-?", "release-1"));
+        assert!(!matches("release-?", "release-10"));
+        assert!(!matches("release-?", "release-"));
+        assert!(!matches("a?b", "a/b"));
+    }
+
+    #[test]
+    fn pattern_is_anchored() {
+        assert!(!matches("core", "platform/core"));
+        assert!(!matches("platform", "platform/core"));
+        assert!(matches("core", "core"));
+    }
+
+    #[test]
+    fn empty_pattern_matches_empty_name() {
+        assert!(matches("", ""));
+        assert!(!matches("", "core"));
+    }
+}
@@ -0,0 +1,26 @@
+use std::process::Command;
+
+/// Runs the post hook command with the output files appended to its arguments. Returns whether
+/// the hook succeeded, failures are reported on stderr.
+pub fn run(hook: &str, files: &[String]) -> bool {
+    let mut parts = hook.split_whitespace();
+    let program = match parts.next() {
+        Some(program) => program,
+        None => {
+            eprintln!("Failed to run post hook: the command is empty");
+            return false;
+        }
+    };
+
+    match Command::new(program).args(parts).args(files).status() {
+        Ok(status) if status.success() => true,
+        Ok(status) => {
+            eprintln!("Post hook '{}' failed: {}", hook, status);
+            false
+        }
+        Err(err) => {
+            eprintln!("Failed to run post hook '{}': {}", hook, err);
+            false
+        }
+    }
+}
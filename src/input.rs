@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+
+use crate::glob;
+use crate::review::{self, Review};
+use crate::schema;
+
+/// Returns files to read for the input path, which can be a file, a directory or a glob pattern
+/// matching file names in a directory, e.g. `raw/*.json`.
+fn input_files(path: &str) -> Vec<PathBuf> {
+    let path = Path::new(path);
+    let file_name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default();
+
+    let (dir, pattern) = if path.is_dir() {
+        (path, "*")
+    } else if file_name.contains('*') || file_name.contains('?') {
+        let dir = match path.parent() {
+            Some(parent) if parent != Path::new("") => parent,
+            _ => Path::new("."),
+        };
+        (dir, file_name)
+    } else {
+        return vec![path.to_path_buf()];
+    };
+
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)
+        .expect("Failed to read input directory")
+        .map(|entry| entry.expect("Failed to read input directory").path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| glob::matches(pattern, name))
+        })
+        .collect();
+
+    files.sort();
+    files
+}
+
+/// Reads reviews from raw `gerrit query --format JSON` output saved in files. Files can hold
+/// output of several queries, stats rows are skipped. With `strict` unknown fields are an error.
+/// The changes are parsed on the given number of threads.
+pub fn read_reviews(path: &str, strict: bool, threads: usize) -> Vec<Review> {
+    let mut outputs = Vec::new();
+
+    for file in input_files(path) {
+        let output = std::fs::read_to_string(&file).expect("Failed to read input file");
+        if strict {
+            schema::check_fields(&output, &file.display().to_string());
+        }
+        outputs.push(output);
+    }
+
+    let outputs: Vec<&str> = outputs.iter().map(String::as_str).collect();
+    review::parse_outputs(&outputs, threads)
+}
@@ -0,0 +1,55 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::sync::Mutex;
+
+/// Path of the lock file this instance holds, so that `exit` can remove it.
+static HELD: Mutex<Option<String>> = Mutex::new(None);
+
+/// Lock file keeping two instances from writing the same output files at once. It's removed when
+/// dropped or on `exit`, a run that crashed leaves it behind.
+pub struct Lock(());
+
+impl Lock {
+    /// Creates the lock file, exiting with an error if it already exists.
+    pub fn acquire(path: &str) -> Self {
+        let mut file = match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                eprintln!(
+                    "Lock file {} already exists, another instance may be writing the same output files.",
+                    path
+                );
+                eprintln!("Remove the file if an earlier run crashed.");
+                std::process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("Failed to create lock file {}: {}", path, err);
+                std::process::exit(1);
+            }
+        };
+        writeln!(file, "{}", std::process::id()).expect("Failed to write lock file");
+        *HELD.lock().expect("Failed to get lock file") = Some(path.to_string());
+        Lock(())
+    }
+}
+
+/// Removes the lock file if this instance still holds it.
+fn release() {
+    let held = HELD.lock().expect("Failed to get lock file").take();
+    if let Some(path) = held {
+        fs::remove_file(path).expect("Failed to remove lock file");
+    }
+}
+
+/// Exits like `std::process::exit`, which doesn't run destructors, removing the lock file first.
+/// Errors after the lock is acquired exit through this, so they don't block the next run.
+pub fn exit(code: i32) -> ! {
+    release();
+    std::process::exit(code)
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        release();
+    }
+}
@@ -1,36 +1,484 @@
+mod batch;
+mod coverage;
+mod custom;
+mod date;
+mod encoding;
+mod explain;
+mod gate;
+mod gini;
+mod glob;
+mod hook;
+mod input;
+mod lock;
+mod output;
+mod profile;
+#[cfg(feature = "pushgateway")]
+mod pushgateway;
+mod query;
 mod review;
+mod schema;
+mod sparkline;
 
-use crate::review::Review;
+use crate::date::{Bucket, Date};
+use crate::review::{Comment, Review};
 use clap::{App, Arg};
 use serde::Deserialize;
-use std::collections::BTreeMap;
-use std::collections::HashMap;
-use toml::value::Datetime;
-
-use futures::future::join_all;
-use std::process::Command;
-use tokio_core::reactor::Core;
-use tokio_process::CommandExt;
+use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+use std::time::Duration;
 
 #[derive(Debug, Deserialize)]
 struct Config {
     server: String,
+    #[serde(deserialize_with = "deserialize_port")]
     port: String,
-    from: Datetime,
-    to: Datetime,
+    from: Date,
+    to: Date,
+    #[serde(default)]
+    columns: Vec<String>,
+    #[serde(default)]
+    column_labels: BTreeMap<String, String>,
+    #[serde(default = "default_average_label")]
+    average_label: String,
+    #[serde(default)]
+    average_row: AverageRow,
+    #[serde(default)]
+    repos: Vec<String>,
+    #[serde(default)]
+    exclude_repos: Vec<String>,
+    #[serde(default)]
+    branches: Vec<String>,
+    #[serde(default)]
+    exclude_changes: Vec<i32>,
+    #[serde(default)]
+    message_glob: Option<String>,
+    #[serde(default)]
+    min_lines: Option<u32>,
+    #[serde(default)]
+    max_lines: Option<u32>,
+    #[serde(default = "default_status")]
+    status: String,
+    #[serde(default)]
+    projects: Vec<String>,
+    #[serde(default)]
+    query_extra: String,
+    #[serde(default)]
+    project_prefix_strip: Option<String>,
+    #[serde(skip)]
+    by_branch: bool,
+    #[serde(default)]
+    approvals_by_grant_date: bool,
+    #[serde(default)]
+    ci_accounts: Vec<String>,
+    #[serde(default)]
+    human_only: bool,
+    #[serde(default = "default_bot_accounts")]
+    bot_accounts: Vec<String>,
+    #[serde(default = "default_bot_messages")]
+    bot_messages: Vec<String>,
+    #[serde(default = "default_approval_labels")]
+    approval_labels: Vec<ApprovalLabel>,
+    #[serde(default)]
+    repo_approval_min: BTreeMap<String, i32>,
+    #[serde(default)]
+    repo_min_approvers: BTreeMap<String, u32>,
+    #[serde(default)]
+    pairs: Vec<ReviewPair>,
+    #[serde(default)]
+    metric: Vec<custom::MetricDefinition>,
+    #[serde(default)]
+    approved_is_reviewed: bool,
+    #[serde(default)]
+    exclude_own_comments: bool,
+    #[serde(default)]
+    score_weights: BTreeMap<String, f64>,
+    #[serde(default)]
+    match_by_email: bool,
+    #[serde(default = "default_comment_decay")]
+    comment_decay: f64,
+    #[serde(default = "default_submit_label")]
+    submit_label: String,
+    #[serde(default = "default_revert_pattern")]
+    revert_pattern: String,
+    #[serde(default)]
+    self_comment_count: SelfCommentCount,
+    #[serde(default)]
+    approval_patch_sets: ApprovalPatchSets,
+    #[serde(default)]
+    attribute_by: AttributeBy,
+    #[serde(skip)]
+    only: Option<String>,
+    #[serde(skip)]
+    bucket: Option<Bucket>,
+    #[serde(skip)]
+    output_prefix: String,
+    #[serde(skip)]
+    label: Option<String>,
+    #[serde(skip)]
+    raw: bool,
+    #[serde(skip)]
+    raw_durations: bool,
+    #[serde(skip)]
+    changes_only: bool,
+    #[serde(skip)]
+    strict_parse: bool,
+    #[serde(skip)]
+    quiet: bool,
+    #[serde(skip)]
+    parse_threads: usize,
+    #[serde(skip)]
+    spawn_delay: Duration,
+    #[serde(skip)]
+    encoding: encoding::Encoding,
+    #[serde(skip)]
+    lossy: bool,
+    #[serde(skip)]
+    normalize_by: Option<output::Normalize>,
+    #[serde(skip)]
+    anonymize: bool,
+    #[serde(skip)]
+    sort_by: Option<output::SortBy>,
+    #[serde(skip)]
+    pivot: Option<String>,
+    #[serde(skip)]
+    top_repos: Option<usize>,
+    #[serde(skip)]
+    score: bool,
+    #[serde(skip)]
+    baseline: Option<Rc<output::Baseline>>,
+    #[serde(skip)]
+    gates: Vec<gate::Gate>,
+    #[serde(skip)]
+    custom_metrics: Vec<custom::CustomMetric>,
     user: Vec<User>,
 }
 
+/// Where the Average row of every period is written in the simple statistics.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum AverageRow {
+    /// Before the user rows.
+    #[default]
+    First,
+    /// After the user rows.
+    Last,
+    /// Not at all.
+    None,
+}
+
+/// How the owner's comments on their own changes are counted in SC.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum SelfCommentCount {
+    /// Every comment counts.
+    #[default]
+    Raw,
+    /// Every patch set the owner commented on counts once, however many comments they wrote.
+    PatchSets,
+}
+
+/// Which patch sets' votes are looked at for approvals.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum ApprovalPatchSets {
+    /// Only the last patch set, for Gerrit servers that copy votes to new patch sets.
+    #[default]
+    Last,
+    /// Every patch set, a vote on any of them approves.
+    All,
+}
+
+/// Who a change is counted for, and which changes are fetched for a user.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum AttributeBy {
+    /// The owner of the change.
+    #[default]
+    Owner,
+    /// The uploader of the last patch set, e.g. someone uploading on behalf of the owner.
+    Uploader,
+    /// The owner if they are a configured user, the uploader of the last patch set otherwise.
+    Both,
+}
+
+/// Reviewer and author whose review flow is reported in `pairs.csv`, e.g. a mentor and mentee.
+#[derive(Debug, Deserialize)]
+struct ReviewPair {
+    reviewer: String,
+    author: String,
+}
+
+/// Label with the minimum value that counts as an approval, e.g. Code-Review +2.
+#[derive(Debug, Deserialize)]
+struct ApprovalLabel {
+    name: String,
+    min: i32,
+}
+
+/// Replaces every `${NAME}` in the string values of the config file with the value of the
+/// environment variable, so that e.g. the server doesn't have to be committed. Comments and keys
+/// are left as they are, an unset variable in a value is an error.
+fn interpolate_env(value: &mut toml::Value) -> Result<(), String> {
+    match value {
+        toml::Value::String(text) => *text = interpolate_str(text)?,
+        toml::Value::Array(values) => {
+            for value in values {
+                interpolate_env(value)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for value in table.values_mut() {
+                interpolate_env(value)?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Replaces the `${NAME}` references of a single string value.
+fn interpolate_str(text: &str) -> Result<String, String> {
+    let mut interpolated = String::with_capacity(text.len());
+
+    let mut rest = text;
+    while let Some(start) = rest.find("${") {
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let name = &rest[start + 2..end];
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!(
+                "invalid environment variable reference '{}'",
+                &rest[start..=end]
+            ));
+        }
+        let value = std::env::var(name)
+            .map_err(|_| format!("environment variable '{}' is not set", name))?;
+
+        interpolated.push_str(&rest[..start]);
+        interpolated.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    interpolated.push_str(rest);
+
+    Ok(interpolated)
+}
+
+/// Finds the `[[user]]` entry that failed to deserialize. Type errors from
+/// toml carry the key path but no line, so each entry is tried on its own.
+fn find_user_entry(config_str: &str, error: &toml::de::Error) -> Option<UserEntry> {
+    if error.line_col().is_some() {
+        return None;
+    }
+
+    let value: toml::Value = toml::from_str(config_str).ok()?;
+    let index = value
+        .get("user")?
+        .as_array()?
+        .iter()
+        .position(|user| user.clone().try_into::<User>().is_err())?;
+
+    let line = config_str
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.trim_start().starts_with("[[user]]"))
+        .nth(index)
+        .map(|(line, _)| line);
+
+    Some(UserEntry { index, line })
+}
+
+/// Accepts the port as an integer or a string, e.g. `29418` or `"29418"`, only valid TCP ports are
+/// passed on to ssh.
+fn deserialize_port<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawPort {
+        Integer(i64),
+        String(String),
+    }
+
+    let port = match RawPort::deserialize(deserializer)? {
+        RawPort::Integer(port) => port.to_string(),
+        RawPort::String(port) => port,
+    };
+
+    match port.parse::<u16>() {
+        Ok(number) if number > 0 => Ok(number.to_string()),
+        _ => Err(serde::de::Error::custom(format!(
+            "invalid port '{}', expected a number between 1 and 65535",
+            port
+        ))),
+    }
+}
+
+fn default_average_label() -> String {
+    "Average".to_string()
+}
+
+fn default_status() -> String {
+    "merged".to_string()
+}
+
+fn default_comment_decay() -> f64 {
+    1.0
+}
+
+fn default_submit_label() -> String {
+    "SUBM".to_string()
+}
+
+fn default_revert_pattern() -> String {
+    "Revert \"**".to_string()
+}
+
+fn default_bot_accounts() -> Vec<String> {
+    vec!["*bot".to_string(), "*-ci".to_string()]
+}
+
+fn default_bot_messages() -> Vec<String> {
+    [
+        "Build Started",
+        "Build Successful",
+        "Build Failed",
+        "Build Unstable",
+    ]
+    .iter()
+    .map(|template| format!("**{}**", template))
+    .collect()
+}
+
+fn default_approval_labels() -> Vec<ApprovalLabel> {
+    vec![ApprovalLabel {
+        name: "Code-Review".to_string(),
+        min: 2,
+    }]
+}
+
+/// Error returned when the config file can't be read or parsed.
+#[derive(Debug)]
+enum ConfigError {
+    Read {
+        path: String,
+        error: std::io::Error,
+    },
+    Parse {
+        path: String,
+        error: toml::de::Error,
+        user: Option<UserEntry>,
+    },
+    Invalid {
+        path: String,
+        message: String,
+    },
+}
+
+/// Position of the `[[user]]` entry a parse error was found in.
+#[derive(Debug)]
+struct UserEntry {
+    index: usize,
+    line: Option<usize>,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::Read { path, error } => write!(f, "{}: {}", path, error),
+            ConfigError::Parse { path, error, user } => {
+                write!(f, "{}: {}", path, error)?;
+                if let Some(user) = user {
+                    write!(f, " in [[user]] entry #{}", user.index + 1)?;
+                    if let Some(line) = user.line {
+                        write!(f, " at line {}", line + 1)?;
+                    }
+                }
+                Ok(())
+            }
+            ConfigError::Invalid { path, message } => write!(f, "{}: {}", path, message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 impl Config {
-    pub fn from_file(file_path: &str) -> Self {
-        let config_str = std::fs::read_to_string(file_path).expect("Failed to read config file");
+    pub fn from_file(file_path: &str) -> Result<Self, ConfigError> {
+        let config_str = std::fs::read_to_string(file_path).map_err(|error| ConfigError::Read {
+            path: file_path.to_string(),
+            error,
+        })?;
+        let parse_error = |error| ConfigError::Parse {
+            path: file_path.to_string(),
+            user: find_user_entry(&config_str, &error),
+            error,
+        };
 
-        let mut config: Config =
-            toml::from_str(config_str.as_str()).expect("Failed to parse config file");
-        config.fill_missing_dates();
-        config
+        let mut value: toml::Value = toml::from_str(config_str.as_str()).map_err(parse_error)?;
+        interpolate_env(&mut value).map_err(|message| ConfigError::Invalid {
+            path: file_path.to_string(),
+            message,
+        })?;
+        let mut config: Config = value.try_into().map_err(parse_error)?;
+
+        if !(0.0..=1.0).contains(&config.comment_decay) {
+            return Err(ConfigError::Invalid {
+                path: file_path.to_string(),
+                message: format!(
+                    "comment_decay must be between 0 and 1, got {}",
+                    config.comment_decay
+                ),
+            });
+        }
+
+        if let Err(message) = config.validate_aliases() {
+            return Err(ConfigError::Invalid {
+                path: file_path.to_string(),
+                message,
+            });
+        }
+
+        if let Err(message) = config.validate_pairs() {
+            return Err(ConfigError::Invalid {
+                path: file_path.to_string(),
+                message,
+            });
+        }
+
+        match config.register_metrics() {
+            Ok(metrics) => config.custom_metrics = metrics,
+            Err(message) => {
+                return Err(ConfigError::Invalid {
+                    path: file_path.to_string(),
+                    message,
+                })
+            }
+        }
+
+        Ok(config)
     }
 
+    /// Returns the custom metrics of the `[[metric]]` entries, checking that their names aren't used
+    /// by built-in metrics.
+    fn register_metrics(&self) -> Result<Vec<custom::CustomMetric>, String> {
+        let metrics = custom::registered(&self.metric)?;
+        let built_in = output::metric_names(self);
+        match metrics
+            .iter()
+            .find(|metric| built_in.contains(&metric.name))
+        {
+            Some(metric) => Err(format!(
+                "metric '{}' is already a built-in metric",
+                metric.name
+            )),
+            None => Ok(metrics),
+        }
+    }
+
+    /// Gives users without their own dates the global ones, which have to be final by then, as
+    /// `--from` and `--to` replace them.
     pub fn fill_missing_dates(&mut self) {
         for user in &mut self.user {
             if user.from.is_none() {
@@ -42,8 +490,8 @@ impl Config {
         }
     }
 
-    fn user_dates(&self) -> HashMap<String, (Datetime, Datetime)> {
-        let mut users: HashMap<String, (Datetime, Datetime)> = HashMap::new();
+    fn user_dates(&self) -> BTreeMap<String, (Date, Date)> {
+        let mut users: BTreeMap<String, (Date, Date)> = BTreeMap::new();
         for user in &self.user {
             users.insert(
                 user.username.clone(),
@@ -53,8 +501,127 @@ impl Config {
         users
     }
 
-    fn user_names(&self) -> HashMap<String, String> {
-        let mut users: HashMap<String, String> = HashMap::new();
+    /// Returns whether changes in the repo should be counted, repos are matched against glob patterns.
+    fn is_counted_repo(&self, repo: &str) -> bool {
+        (self.repos.is_empty() || glob::matches_any(&self.repos, repo))
+            && !glob::matches_any(&self.exclude_repos, repo)
+    }
+
+    /// Returns whether changes on the branch should be counted, branches are matched against glob
+    /// patterns.
+    fn is_counted_branch(&self, branch: &str) -> bool {
+        self.branches.is_empty() || glob::matches_any(&self.branches, branch)
+    }
+
+    /// Returns whether the change is counted by its repo, branch and number, regardless of dates.
+    fn is_counted_review(&self, review: &Review) -> bool {
+        !self.exclude_changes.contains(&review.number)
+            && self.is_counted_repo(&review.repository_name())
+            && self.is_counted_branch(&review.branch)
+            && self
+                .message_glob
+                .as_ref()
+                .is_none_or(|pattern| glob::matches(pattern, review.commit_message()))
+            && self.is_counted_size(review)
+    }
+
+    /// Returns whether the lines changed by the review are within `min_lines` and `max_lines`.
+    /// Without size data a review is only counted if neither is set.
+    fn is_counted_size(&self, review: &Review) -> bool {
+        if self.min_lines.is_none() && self.max_lines.is_none() {
+            return true;
+        }
+        review.lines_changed().is_some_and(|lines| {
+            self.min_lines.is_none_or(|min| lines >= min)
+                && self.max_lines.is_none_or(|max| lines <= max)
+        })
+    }
+
+    /// Returns the canonical username of every alias, and of every lowercase email when matching
+    /// users by email.
+    fn user_aliases(&self) -> BTreeMap<String, String> {
+        let mut aliases = BTreeMap::new();
+        for user in &self.user {
+            for alias in &user.aliases {
+                aliases.insert(alias.clone(), user.username.clone());
+            }
+            if let (true, Some(email)) = (self.match_by_email, &user.email) {
+                aliases.insert(email.to_lowercase(), user.username.clone());
+            }
+        }
+        aliases
+    }
+
+    /// Returns an error message if an alias is used more than once or is also a username.
+    fn validate_aliases(&self) -> Result<(), String> {
+        let mut seen: HashSet<&String> = self.user.iter().map(|user| &user.username).collect();
+        for alias in self.user.iter().flat_map(|user| &user.aliases) {
+            if !seen.insert(alias) {
+                return Err(format!("alias '{}' is already used by another user", alias));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether the comment was posted by a machine for `human_only`: by a CI account, an
+    /// account matching `bot_accounts`, or with a message matching `bot_messages`.
+    fn is_bot_comment(&self, comment: &Comment) -> bool {
+        let username = &comment.reviewer.username;
+        self.ci_accounts.contains(username)
+            || glob::matches_any(&self.bot_accounts, username)
+            || glob::matches_any(&self.bot_messages, &comment.message)
+    }
+
+    /// Returns an error message if a pair names a user that isn't configured.
+    fn validate_pairs(&self) -> Result<(), String> {
+        for pair in &self.pairs {
+            for username in [&pair.reviewer, &pair.author] {
+                if !self.user.iter().any(|user| &user.username == username) {
+                    return Err(format!(
+                        "user '{}' of a pair is not in the config file",
+                        username
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Keeps only the first users of the config file, with the pairs among them.
+    fn limit_users(&mut self, count: usize) {
+        self.user.truncate(count);
+        let users = self.user_names();
+        self.pairs
+            .retain(|pair| users.contains_key(&pair.reviewer) && users.contains_key(&pair.author));
+    }
+
+    /// Returns whether the reviewer and author are one of the configured pairs.
+    fn is_pair(&self, reviewer: &str, author: &str) -> bool {
+        self.pairs
+            .iter()
+            .any(|pair| pair.reviewer == reviewer && pair.author == author)
+    }
+
+    /// Returns whether the user's rows should be written to the output.
+    fn is_reported(&self, username: &str) -> bool {
+        self.only.as_ref().is_none_or(|only| only == username)
+    }
+
+    /// Returns stable pseudonyms of all users, numbered in username order.
+    fn user_pseudonyms(&self) -> BTreeMap<String, String> {
+        let mut usernames: Vec<&String> = self.user.iter().map(|user| &user.username).collect();
+        usernames.sort();
+        usernames.dedup();
+
+        usernames
+            .into_iter()
+            .enumerate()
+            .map(|(index, username)| (username.clone(), format!("user-{}", index + 1)))
+            .collect()
+    }
+
+    fn user_names(&self) -> BTreeMap<String, String> {
+        let mut users: BTreeMap<String, String> = BTreeMap::new();
         for user in &self.user {
             users.insert(user.username.clone(), user.fullname.clone());
         }
@@ -66,20 +633,79 @@ impl Config {
 struct User {
     username: String,
     fullname: String,
-    from: Option<Datetime>,
-    to: Option<Datetime>,
+    #[serde(default)]
+    aliases: Vec<String>,
+    email: Option<String>,
+    from: Option<Date>,
+    to: Option<Date>,
 }
 
 type UserStatistics = BTreeMap<String, BTreeMap<String, Stats>>;
+type PeriodStatistics = BTreeMap<String, UserStatistics>;
 
 #[derive(Debug, Default)]
 struct Stats {
     changes: u32,
+    merged_changes: u32,
+    unreviewed_changes: u32,
     approvals: u32,
     comments_made: u32,
     comments_received: u32,
+    comments_received_from_others: u32,
+    comments_received_resolved: u32,
+    comments_received_unresolved: u32,
+    comments_received_unknown: u32,
+    /// Inline comments received on lines the change added, see `Review::comments_received_on_change`.
+    comments_received_on_change: u32,
+    comments_received_inline: u32,
+    weighted_comments_received: f64,
     commit_words: u32,
     patch_sets: u32,
+    substantive_patch_sets: u32,
+    first_responses: u32,
+    first_response_time: u64,
+    author_turnarounds: u32,
+    author_turnaround_time: u64,
+    contributors: u32,
+    self_comments: u32,
+    stack_depth: u32,
+    max_stack_depth: u32,
+    ci_verifications: u32,
+    ping_pong: u32,
+    ping_pong_changes: u32,
+    threads: u32,
+    thread_comments: u32,
+    self_merges: u32,
+    reverts: u32,
+    /// Approvals the user gave on changes that were reverted later.
+    reverted_approvals: u32,
+    wip_ready: u32,
+    /// Days since the epoch, in UTC, the user took any action on.
+    active_dates: BTreeSet<i64>,
+    active_days: u32,
+    /// Days since the epoch, in UTC, the user uploaded a patch set on.
+    upload_dates: BTreeSet<i64>,
+    upload_days: u32,
+    /// When the changes the user reviewed were open, see `Review::open_interval`.
+    review_intervals: Vec<(i64, i64)>,
+    peak_reviews: u32,
+    /// Usernames of everyone that reviewed the user's changes.
+    reviewers: BTreeSet<String>,
+    distinct_reviewers: u32,
+    /// Number of repos the user commented on or approved changes in.
+    repo_breadth: u32,
+    label_approvals: BTreeMap<String, u32>,
+    /// Number of approvals given in each of `date::DELAY_BUCKETS` after the patch set upload.
+    approval_delays: [u32; 4],
+    /// Number of changes the user last voted each of `review::CODE_REVIEW_VOTES` on.
+    code_review_votes: [u32; 4],
+    /// Values of the registered custom metrics by name.
+    custom: BTreeMap<String, u32>,
+    /// Comments and approvals the user gave on the changes of each author they're paired with, see
+    /// `pairs`.
+    pair_reviews: BTreeMap<String, (u32, u32)>,
+    /// Per-branch breakdown of repo rows, only collected with `--by-branch`.
+    branches: BTreeMap<String, Stats>,
 }
 
 impl Stats {
@@ -91,7 +717,9 @@ impl Stats {
 }
 
 fn main() {
-    let matches = App::new("gerrit-stats")
+    let started = std::time::Instant::now();
+
+    let app = App::new("gerrit-stats")
         .version("0.1.0")
         .author("Radek Szymanski <radszy@pm.me>")
         .about("\nGathers basic statistics based on the reviews users participated in.")
@@ -102,101 +730,959 @@ fn main() {
                 .value_name("FILE")
                 .help("Path to a config file")
                 .takes_value(true)
-                .required(true),
+                .required_unless("config-dir")
+                .conflicts_with("config-dir"),
         )
         .arg(
-            Arg::with_name("user")
+            Arg::with_name("config-dir")
+                .long("config-dir")
+                .value_name("DIR")
+                .help("Run a separate report for every *.toml config file in the directory")
+                .takes_value(true)
+                .conflicts_with_all(&[
+                    "anonymize-map",
+                    "emit-users",
+                    "approval-times",
+                    "vote-histogram",
+                    "per-change",
+                ]),
+        )
+        .arg(
+            Arg::with_name("output-prefix")
+                .long("output-prefix")
+                .value_name("PREFIX")
+                .help("Prefix of the output file names, e.g. team-a- for team-a-stats.csv")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("label")
+                .long("label")
+                .value_name("LABEL")
+                .help("Label added to the output file names, e.g. 2024Q1 for stats-2024Q1.csv")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ssh-user")
                 .short("u")
+                .long("ssh-user")
+                .value_name("NAME")
+                .help("Username used to log in to the Gerrit server over ssh")
+                .takes_value(true)
+                .required_unless_one(&["user", "input"])
+                .conflicts_with("user"),
+        )
+        .arg(
+            Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .value_name("PATH")
+                .help("Read Gerrit query JSON from a file, directory or glob pattern instead of querying")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("user")
                 .long("user")
                 .value_name("NAME")
-                .help("Username for fetching Gerrit changes")
+                .help("Deprecated alias of --ssh-user")
+                .takes_value(true)
+                .hidden(true),
+        )
+        .arg(
+            Arg::with_name("status")
+                .long("status")
+                .value_name("STATUS")
+                .help("Status of the queried changes, e.g. merged, open or abandoned, 'all' queries every status")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("projects")
+                .long("projects")
+                .value_name("LIST")
+                .help("Comma separated list of projects to query, instead of fetching all of the changes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("query-extra")
+                .long("query-extra")
+                .value_name("QUERY")
+                .help("Extra Gerrit query predicates appended to the generated ones")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("repos")
+                .long("repos")
+                .value_name("LIST")
+                .help("Comma separated list of repos to count, glob patterns like 'platform/*' are allowed")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("branches")
+                .long("branches")
+                .value_name("LIST")
+                .help("Comma separated list of branches to count, glob patterns like 'release-*' are allowed")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("message-glob")
+                .long("message-glob")
+                .value_name("PATTERN")
+                .help("Only count changes with a commit message matching the glob (not regex) pattern, e.g. '**PROJ-**'")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("min-lines")
+                .long("min-lines")
+                .value_name("N")
+                .help("Only count changes with at least N inserted and deleted lines")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-lines")
+                .long("max-lines")
+                .value_name("N")
+                .help("Only count changes with at most N inserted and deleted lines")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("by-branch")
+                .long("by-branch")
+                .help("Break detailed statistics down by branch"),
+        )
+        .arg(
+            Arg::with_name("bucket")
+                .long("bucket")
+                .value_name("PERIOD")
+                .help("Group changes by the calendar period they were submitted in")
+                .takes_value(true)
+                .possible_values(Bucket::NAMES),
+        )
+        .arg(
+            Arg::with_name("project-prefix-strip")
+                .long("project-prefix-strip")
+                .value_name("PREFIX")
+                .help("Strip the prefix from repo names in the output, 'auto' strips the common prefix")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("validate-output")
+                .long("validate-output")
+                .help("Read the statistics back after writing them and check every row is there"),
+        )
+        .arg(
+            Arg::with_name("output-encoding")
+                .long("output-encoding")
+                .value_name("ENCODING")
+                .help("Character encoding of the output files, default is utf-8")
+                .takes_value(true)
+                .possible_values(encoding::Encoding::NAMES),
+        )
+        .arg(
+            Arg::with_name("lossy")
+                .long("lossy")
+                .help("Replace characters the output encoding can't represent with '?'"),
+        )
+        .arg(
+            Arg::with_name("merge-into")
+                .long("merge-into")
+                .value_name("FILE")
+                .help("Append the simple statistics with the run date to a CSV file collecting all runs")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("baseline")
+                .long("baseline")
+                .value_name("FILE")
+                .help("Add columns with the change of every metric since the run that wrote the stats file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("post-hook")
+                .long("post-hook")
+                .value_name("COMMAND")
+                .help("Run the command with the written output files as arguments when done")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("force")
+                .long("force")
+                .help("Overwrite output files that already exist"),
+        )
+        .arg(
+            Arg::with_name("lock")
+                .long("lock")
+                .help("Exit with an error if another instance is writing the same output files"),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .conflicts_with("verbose")
+                .help("Don't print progress messages, only warnings and errors"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Also print diagnostics, e.g. the dates each user's fetched changes cover"),
+        )
+        .arg(
+            Arg::with_name("raw")
+                .long("raw")
+                .help("Leave out ratio and average columns, only write counts"),
+        )
+        .arg(
+            Arg::with_name("parse-threads")
+                .long("parse-threads")
+                .value_name("N")
+                .help("Parse the fetched changes on N threads, defaults to the number of CPUs")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("spawn-delay")
+                .long("spawn-delay")
+                .value_name("MS")
+                .help("Wait the given milliseconds between starting the queries of two users")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("strict-parse")
+                .long("strict-parse")
+                .help("Fail when Gerrit's output has fields that aren't known, e.g. after a Gerrit upgrade"),
+        )
+        .arg(
+            Arg::with_name("changes-only")
+                .long("changes-only")
+                .help("Only count changes, patch sets and commit words, without fetching comments"),
+        )
+        .arg(
+            Arg::with_name("normalize-by")
+                .long("normalize-by")
+                .value_name("UNIT")
+                .help("Add count columns divided by the given unit, e.g. per active day")
+                .takes_value(true)
+                .possible_values(output::Normalize::NAMES),
+        )
+        .arg(
+            Arg::with_name("anonymize")
+                .long("anonymize")
+                .help("Replace user names in the output with pseudonyms like user-1"),
+        )
+        .arg(
+            Arg::with_name("anonymize-map")
+                .long("anonymize-map")
+                .value_name("FILE")
+                .help("Write the pseudonym to user mapping to a file")
+                .takes_value(true)
+                .requires("anonymize"),
+        )
+        .arg(
+            Arg::with_name("emit-users")
+                .long("emit-users")
+                .value_name("FILE")
+                .help("Write the usernames, fullnames and dates of the reported users to a file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("approval-times")
+                .long("approval-times")
+                .value_name("FILE")
+                .help("Write how long after the patch set upload every user gave their approvals to a file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("vote-histogram")
+                .long("vote-histogram")
+                .value_name("FILE")
+                .help("Write how many changes every user voted -2, -1, +1 and +2 on in Code-Review to a file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("per-change")
+                .long("per-change")
+                .value_name("FILE")
+                .help("Write a row with the numbers of every counted change to a file")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("raw-durations")
+                .long("raw-durations")
+                .help("Show profile and explain durations in seconds instead of units like 2d 4h"),
+        )
+        .arg(
+            Arg::with_name("explain-user")
+                .long("explain-user")
+                .value_name("USERNAME")
+                .help("Write the changes and reviews counted for the user to explain-<USERNAME>.txt")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("sparklines")
+                .long("sparklines")
+                .help("Print a sparkline of the changes per week of every user"),
+        )
+        .arg(
+            Arg::with_name("gini")
+                .long("gini")
+                .help("Print how unequally changes and reviews are spread across the team"),
+        )
+        .arg(
+            Arg::with_name("stats-line")
+                .long("stats-line")
+                .help("Print a JSON summary of the run to stderr when done"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .long("profile")
+                .help("Print how long each phase of the run took to stderr when done"),
+        )
+        .arg(
+            Arg::with_name("approvals-by-grant-date")
+                .long("approvals-by-grant-date")
+                .help("Count approvals by the date they were given in, using the approver's dates"),
+        )
+        .arg(
+            Arg::with_name("sort-by")
+                .long("sort-by")
+                .value_name("COLUMN[:asc|desc]")
+                .help("Sort rows by the given column, descending unless ':asc' is given")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("pivot")
+                .long("pivot")
+                .value_name("COLUMN")
+                .help("Also write pivot.csv with the column's value for every repo and user")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("top-repos")
+                .long("top-repos")
+                .value_name("N")
+                .help("Also write repos.csv with the N repos with the most changes, comments and approvals")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("from")
+                .long("from")
+                .value_name("DATE")
+                .help("Count changes from the date instead of the config file's from, e.g. 2019-01-01")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("to")
+                .long("to")
+                .value_name("DATE")
+                .help("Count changes until the date instead of the config file's to, e.g. 2019-12-31")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fail-under")
+                .long("fail-under")
+                .value_name("GATE")
+                .help("Exit with code 2 unless the team total meets the gate, e.g. comments_made=50")
+                .long_help(
+                    "Exit with code 2 unless the team total meets the gate, e.g. comments_made=50. \
+                     Other operators are >=, >, <= and <, prefix the gate with 'each:' to check \
+                     every user instead of the team total. Can be given multiple times, all gates \
+                     have to be met.",
+                )
                 .takes_value(true)
-                .required(true),
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("max-users")
+                .long("max-users")
+                .value_name("N")
+                .help("Only process the first N users of the config file, for testing")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("only")
+                .long("only")
+                .value_name("USERNAME")
+                .help("Only output statistics of the given configured user")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("score")
+                .long("score")
+                .help("Add a Score column, a weighted sum of the columns in score_weights of the config file"),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("columns")
+                .long("columns")
+                .value_name("LIST")
+                .help("Comma separated list of columns to output, e.g. User,Repo,CH,CM,CR")
+                .takes_value(true),
+        );
+
+    #[cfg(feature = "pushgateway")]
+    let app = app.arg(
+        Arg::with_name("pushgateway")
+            .long("pushgateway")
+            .value_name("URL")
+            .help("Push statistics to a Prometheus pushgateway, e.g. http://localhost:9091")
+            .takes_value(true),
+    );
+
+    let matches = app.get_matches();
+
+    if let Some(dir) = matches.value_of("config-dir") {
+        std::process::exit(batch::run_config_dir(
+            dir,
+            matches.value_of("output-prefix").unwrap_or_default(),
+            matches.is_present("quiet"),
+        ));
+    }
 
     let config_file = matches
         .value_of("config")
         .expect("Failed to read config option");
 
-    let config = Config::from_file(config_file);
+    let mut config = match Config::from_file(config_file) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Invalid config: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    // Dates given on the command line replace the global dates of the config file, users with
+    // their own dates keep them.
+    for (name, date) in [("from", &mut config.from), ("to", &mut config.to)] {
+        if let Some(value) = matches.value_of(name) {
+            match value.parse() {
+                Ok(value) => *date = value,
+                Err(err) => {
+                    eprintln!("Invalid {} option: {}", name, err);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+    config.fill_missing_dates();
+
+    if let Some(status) = matches.value_of("status") {
+        config.status = status.to_string();
+    }
 
-    let cmd_user = matches
-        .value_of("user")
-        .expect("Failed to read user option");
+    if let Some(projects) = matches.value_of("projects") {
+        config.projects = output::split_list(projects);
+    }
+    if let Err(err) = query::validate_projects(&config.projects) {
+        eprintln!("Invalid projects option: {}", err);
+        std::process::exit(1);
+    }
 
-    let cmd_args = [
-        "-p",
-        config.port.as_str(),
-        &format!("{}@{}", cmd_user, config.server),
-        "gerrit",
-        "query",
-    ];
+    if let Some(query) = matches.value_of("query-extra") {
+        config.query_extra = query.to_string();
+    }
+
+    if let Some(prefix) = matches.value_of("project-prefix-strip") {
+        config.project_prefix_strip = Some(prefix.to_string());
+    }
+
+    config.output_prefix = matches
+        .value_of("output-prefix")
+        .unwrap_or_default()
+        .to_string();
+
+    if let Some(label) = matches.value_of("label") {
+        match output::sanitize_label(label) {
+            Ok(label) => config.label = Some(label),
+            Err(err) => {
+                eprintln!("Invalid label option: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    config.quiet = matches.is_present("quiet");
+
+    config.parse_threads = match matches.value_of("parse-threads") {
+        Some(threads) => match threads.parse() {
+            Ok(threads) if threads > 0 => threads,
+            Ok(_) => {
+                eprintln!("Invalid parse-threads option: at least one thread is needed");
+                std::process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("Invalid parse-threads option: {}", err);
+                std::process::exit(1);
+            }
+        },
+        None => std::thread::available_parallelism().map_or(1, usize::from),
+    };
+
+    config.raw = matches.is_present("raw");
+    config.raw_durations = matches.is_present("raw-durations");
+
+    config.changes_only = matches.is_present("changes-only");
+    config.strict_parse = matches.is_present("strict-parse");
+
+    if let Some(delay) = matches.value_of("spawn-delay") {
+        match delay.parse() {
+            Ok(delay) => config.spawn_delay = Duration::from_millis(delay),
+            Err(err) => {
+                eprintln!("Invalid spawn-delay option: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(encoding) = matches.value_of("output-encoding") {
+        config.encoding = encoding
+            .parse()
+            .expect("Failed to parse output-encoding option");
+    }
+    config.lossy = matches.is_present("lossy");
+
+    config.normalize_by = matches
+        .value_of("normalize-by")
+        .map(|unit| unit.parse().expect("Failed to parse normalize-by option"));
+
+    if let Some(columns) = matches.value_of("columns") {
+        config.columns = output::split_list(columns);
+    }
+
+    config.score = matches.is_present("score");
+    if config.score {
+        if let Err(err) = output::validate_score(&config) {
+            eprintln!("Invalid score: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if let Err(err) = output::validate_columns(&config) {
+        eprintln!("Invalid columns: {}", err);
+        std::process::exit(1);
+    }
+
+    if let Some(repos) = matches.value_of("repos") {
+        config.repos = output::split_list(repos);
+    }
+
+    if let Some(branches) = matches.value_of("branches") {
+        config.branches = output::split_list(branches);
+    }
+
+    if let Some(pattern) = matches.value_of("message-glob") {
+        config.message_glob = Some(pattern.to_string());
+    }
+
+    for (name, lines) in [
+        ("min-lines", &mut config.min_lines),
+        ("max-lines", &mut config.max_lines),
+    ] {
+        if let Some(value) = matches.value_of(name) {
+            match value.parse() {
+                Ok(value) => *lines = Some(value),
+                Err(err) => {
+                    eprintln!("Invalid {} option: {}", name, err);
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+
+    config.by_branch = matches.is_present("by-branch");
+
+    config.bucket = matches
+        .value_of("bucket")
+        .map(|bucket| bucket.parse().expect("Failed to parse bucket option"));
+
+    if matches.is_present("approvals-by-grant-date") {
+        config.approvals_by_grant_date = true;
+    }
+
+    if let Some(sort_by) = matches.value_of("sort-by") {
+        match output::parse_sort_by(sort_by, &config) {
+            Ok(sort_by) => config.sort_by = Some(sort_by),
+            Err(err) => {
+                eprintln!("Invalid sort-by option: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(pivot) = matches.value_of("pivot") {
+        match output::parse_pivot(pivot, &config) {
+            Ok(pivot) => config.pivot = Some(pivot),
+            Err(err) => {
+                eprintln!("Invalid pivot option: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(top_repos) = matches.value_of("top-repos") {
+        match top_repos.parse() {
+            Ok(top_repos) => config.top_repos = Some(top_repos),
+            Err(err) => {
+                eprintln!("Invalid top-repos option: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    for gate in matches.values_of("fail-under").into_iter().flatten() {
+        match gate::parse_gate(gate, &config) {
+            Ok(gate) => config.gates.push(gate),
+            Err(err) => {
+                eprintln!("Invalid fail-under option: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    config.anonymize = matches.is_present("anonymize");
 
-    let cmd_opts = [
-        "--all-approvals",
-        "--all-reviewers",
-        "--comments",
-        "--commit-message",
-        "--files",
-        "--format",
-        "JSON",
+    if let Some(max_users) = matches.value_of("max-users") {
+        match max_users.parse() {
+            Ok(max_users) => config.limit_users(max_users),
+            Err(err) => {
+                eprintln!("Invalid max-users option: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    config.only = matches.value_of("only").map(str::to_string);
+
+    if let Some(only) = &config.only {
+        if !config.user.iter().any(|user| &user.username == only) {
+            eprintln!("User '{}' passed to --only is not in the config file", only);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(user) = matches.value_of("explain-user") {
+        if !config
+            .user
+            .iter()
+            .any(|configured| configured.username == user)
+        {
+            eprintln!(
+                "User '{}' passed to --explain-user is not in the config file",
+                user
+            );
+            std::process::exit(1);
+        }
+    }
+
+    if matches.is_present("user") {
+        eprintln!("Warning: --user is deprecated, use --ssh-user instead.");
+    }
+
+    if let Some(baseline_file) = matches.value_of("baseline") {
+        match output::read_baseline(baseline_file, &config) {
+            Ok(baseline) => config.baseline = Some(Rc::new(baseline)),
+            Err(err) => {
+                eprintln!("Invalid baseline: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(baseline) = &config.baseline {
+        let names = output::display_names(&config);
+        let missing: Vec<&str> = baseline
+            .users()
+            .into_iter()
+            .filter(|user| {
+                *user != config.average_label && !names.values().any(|name| name == user)
+            })
+            .collect();
+        if !missing.is_empty() {
+            eprintln!(
+                "Warning: users in the baseline but not in this run: {}",
+                missing.join(", ")
+            );
+        }
+    }
+
+    if let Some(merge_file) = matches.value_of("merge-into") {
+        if let Err(err) = output::validate_merge_file(merge_file, &config) {
+            eprintln!("Invalid merge file: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    let mut output_files = vec![
+        output::output_file("stats", &config),
+        output::output_file("detailed", &config),
     ];
+    if config.pivot.is_some() {
+        output_files.push(output::output_file("pivot", &config));
+    }
+    if config.top_repos.is_some() {
+        output_files.push(output::output_file("repos", &config));
+    }
+    if !config.pairs.is_empty() {
+        output_files.push(output::output_file("pairs", &config));
+    }
+    let violations_file = output::output_path("violations", "csv", &config);
+    if !config.repo_min_approvers.is_empty() {
+        output_files.push(violations_file.clone());
+    }
+    output_files.extend(matches.value_of("anonymize-map").map(str::to_string));
+    output_files.extend(matches.value_of("emit-users").map(str::to_string));
+    output_files.extend(matches.value_of("approval-times").map(str::to_string));
+    output_files.extend(matches.value_of("vote-histogram").map(str::to_string));
+    output_files.extend(matches.value_of("per-change").map(str::to_string));
+    let explain_file = matches.value_of("explain-user").map(|user| {
+        let user = &output::shown_usernames(&config)[user];
+        output::output_path(&format!("explain-{}", user), "txt", &config)
+    });
+    output_files.extend(explain_file.clone());
 
-    let mut cmds = Vec::new();
+    if !matches.is_present("force") {
+        let existing: Vec<&String> = output_files
+            .iter()
+            .filter(|file| std::path::Path::new(file).exists())
+            .collect();
+        if !existing.is_empty() {
+            for file in existing {
+                eprintln!("Output file {} already exists.", file);
+            }
+            eprintln!(
+                "Pass --force to overwrite existing files, or --output-prefix to write elsewhere."
+            );
+            std::process::exit(1);
+        }
+    }
 
-    println!("Spawning {} async tasks.", config.user.len());
+    let lock = matches
+        .is_present("lock")
+        .then(|| lock::Lock::acquire(&format!("{}gerrit-stats.lock", config.output_prefix)));
+
+    let mut profile = profile::Profile::default();
+
+    let (mut reviews, failed_queries, interrupted) = match matches.value_of("input") {
+        Some(path) => (
+            profile.time("read input", || {
+                input::read_reviews(path, config.strict_parse, config.parse_threads)
+            }),
+            0,
+            false,
+        ),
+        None => {
+            let ssh_user = matches
+                .value_of("ssh-user")
+                .or_else(|| matches.value_of("user"))
+                .expect("Failed to read ssh-user option");
+            query::query_reviews(&config, ssh_user, &mut profile)
+        }
+    };
 
-    for user in &config.user {
-        let child = Command::new("ssh")
-            .stdout(std::process::Stdio::piped())
-            .args(&cmd_args)
-            .args(&cmd_opts)
-            .arg("status:merged")
-            .arg(format!("after:{}", user.from.clone().unwrap()))
-            .arg(format!("before:{}", user.to.clone().unwrap()))
-            .arg(format!("owner:{}", user.username))
-            .spawn_async()
-            .expect("Failed to spawn command")
-            .wait_with_output();
+    let stats = profile.time("collect", || {
+        prepare_reviews(&mut reviews, &config);
+        collect_period_stats(&reviews, &config)
+    });
 
-        cmds.push(child);
+    if matches.is_present("verbose") {
+        coverage::print(&reviews, &config);
     }
 
-    println!("Starting work. This might take a while.");
+    profile.time("output", || {
+        output::write_simple_stats(&stats, &config);
+        if config.pivot.is_some() {
+            output::write_pivot(&stats, &config);
+        }
+        if let Some(count) = config.top_repos {
+            output::write_top_repos(&stats, count, &config);
+        }
+        if !config.pairs.is_empty() {
+            output::write_pairs(&stats, &config);
+        }
+        if let Some(merge_file) = matches.value_of("merge-into") {
+            output::merge_simple_stats(merge_file, &stats, &config);
+        }
+        output::write_detailed_stats(&stats, &config);
+    });
+
+    if matches.is_present("validate-output") {
+        if let Err(err) = output::validate_output(&stats, &config) {
+            eprintln!("Invalid output: {}", err);
+            lock::exit(1);
+        }
+    }
+
+    if let Some(map_file) = matches.value_of("anonymize-map") {
+        output::write_pseudonyms(map_file, &config);
+    }
+
+    if let Some(users_file) = matches.value_of("emit-users") {
+        output::write_users(users_file, &config);
+    }
+
+    if let Some(delays_file) = matches.value_of("approval-times") {
+        output::write_approval_delays(delays_file, &stats, &config);
+    }
+
+    if let Some(votes_file) = matches.value_of("vote-histogram") {
+        output::write_vote_histogram(votes_file, &stats, &config);
+    }
 
-    let work = join_all(cmds);
-    let mut core = Core::new().expect("Failed to create reactor");
-    let ret = core.run(work).expect("Failed to run work");
+    if let Some(changes_file) = matches.value_of("per-change") {
+        output::write_changes(changes_file, &reviews, &config);
+    }
+
+    if let (Some(user), Some(explain_file)) = (matches.value_of("explain-user"), &explain_file) {
+        explain::write_explanation(explain_file, &reviews, user, &config);
+    }
+
+    if !config.repo_min_approvers.is_empty() {
+        output::write_violations(&violations_file, &reviews, &config);
+    }
+
+    if matches.is_present("sparklines") {
+        sparkline::print(&reviews, &config);
+    }
 
-    let mut reviews = Vec::new();
+    if matches.is_present("gini") {
+        gini::print(&stats, &config);
+    }
 
-    for output in &ret {
-        let output = std::str::from_utf8(&output.stdout).expect("Failed to read command output");
-        for line in output.lines().rev().skip(1) {
-            let rev = Review::new(line);
-            reviews.push(rev);
+    let failures = gate::check_gates(&stats, &config);
+    for failure in &failures {
+        eprintln!("Gate not met: {}", failure);
+    }
+
+    #[cfg(feature = "pushgateway")]
+    {
+        if let Some(url) = matches.value_of("pushgateway") {
+            if let Err(err) = pushgateway::push(url, &stats, &config) {
+                eprintln!("Failed to push statistics: {}", err);
+                lock::exit(1);
+            }
         }
     }
 
-    let stats = collect_stats(&reviews, &config);
-    write_simple_stats(&stats, &config);
-    write_detailed_stats(&stats, &config);
+    if let Some(merge_file) = matches.value_of("merge-into") {
+        output_files.push(merge_file.to_string());
+    }
+    let hook_failed = match matches.value_of("post-hook") {
+        Some(hook) => profile.time("post hook", || !hook::run(hook, &output_files)),
+        None => false,
+    };
+    drop(lock);
+
+    if matches.is_present("profile") {
+        profile.add("total", started.elapsed());
+        profile.print(config.raw_durations);
+    }
+
+    if matches.is_present("stats-line") {
+        let mut summary = serde_json::json!({
+            "users": config.user.len(),
+            "reviews": reviews.len(),
+            "failed_queries": failed_queries,
+            "interrupted": interrupted,
+            "elapsed_ms": started.elapsed().as_millis() as u64,
+            "label": config.label,
+        });
+        if matches.is_present("gini") {
+            summary["gini"] = gini::to_json(&stats, &config);
+        }
+        eprintln!("{}", summary);
+    }
+
+    if hook_failed {
+        std::process::exit(1);
+    }
+
+    if interrupted {
+        std::process::exit(3);
+    }
+
+    if !failures.is_empty() {
+        std::process::exit(2);
+    }
+}
+
+/// Makes the user every change is counted for its owner, see `AttributeBy`. Changes fetched by the
+/// queries of more than one user are only kept once.
+fn attribute_changes(reviews: &mut Vec<Review>, config: &Config) {
+    if config.attribute_by == AttributeBy::Owner {
+        return;
+    }
+
+    // Changes whose uploader isn't a configured user stay with their owner with "both", and aren't
+    // counted for anyone with "uploader".
+    let users = config.user_names();
+    reviews.retain_mut(|review| {
+        let uploader = match review.uploader() {
+            Some(uploader) if users.contains_key(&uploader.username) => uploader.clone(),
+            Some(_) => return config.attribute_by != AttributeBy::Uploader,
+            None => return true,
+        };
+        if config.attribute_by == AttributeBy::Uploader
+            || !users.contains_key(&review.owner.username)
+        {
+            review.owner = uploader;
+        }
+        true
+    });
+
+    let mut seen = HashSet::new();
+    reviews.retain(|review| seen.insert(review.number));
 }
 
-fn collect_stats(reviews: &[Review], config: &Config) -> UserStatistics {
-    fn add_stats(
-        stats: &mut BTreeMap<String, Stats>,
-        repo: String,
-        received: u32,
-        patches: u32,
-        words: u32,
+/// Resolves the accounts of the fetched or read changes to the configured users and drops the
+/// changes that aren't counted for any of them, e.g. changes of other owners in an input file or of
+/// users left out by `--max-users`.
+fn prepare_reviews(reviews: &mut Vec<Review>, config: &Config) {
+    review::resolve_users(reviews, &config.user_aliases());
+    attribute_changes(reviews, config);
+
+    let users = config.user_names();
+    reviews.retain(|review| users.contains_key(&review.owner.username));
+
+    if config.human_only {
+        review::remove_comments(reviews, |comment| config.is_bot_comment(comment));
+    }
+    review::set_stack_depths(reviews);
+    review::set_reverted(reviews);
+}
+
+/// Splits reviews by the period they were submitted in and collects stats for each of them.
+/// Without bucketing all reviews end up in a single "All" period.
+fn collect_period_stats(reviews: &[Review], config: &Config) -> PeriodStatistics {
+    let mut periods: BTreeMap<String, Vec<&Review>> = BTreeMap::new();
+
+    for review in reviews {
+        let period = match (config.bucket, review.counted_time(&config.submit_label)) {
+            (Some(bucket), Some(submitted)) => bucket.period(submitted),
+            _ => "All".to_string(),
+        };
+        periods.entry(period).or_default().push(review);
+    }
+
+    periods
+        .into_iter()
+        .map(|(period, reviews)| (period, collect_stats(&reviews, config)))
+        .filter(|(_, stats)| !stats.is_empty())
+        .collect()
+}
+
+fn collect_stats(reviews: &[&Review], config: &Config) -> UserStatistics {
+    fn update_stats<F: Fn(&mut Stats)>(
+        stats: &mut UserStatistics,
+        user: &str,
+        repo: &str,
+        branch: Option<&str>,
+        f: F,
     ) {
-        let total_stats = stats.entry(repo).or_insert_with(Stats::new);
-        total_stats.changes += 1;
-        total_stats.comments_received += received;
-        total_stats.patch_sets += patches;
-        total_stats.commit_words += words;
+        let user_stats = stats.entry(user.to_string()).or_default();
+        f(user_stats.entry("All".to_string()).or_default());
+
+        let repo_stats = user_stats.entry(repo.to_string()).or_default();
+        f(repo_stats);
+        if let Some(branch) = branch {
+            f(repo_stats.branches.entry(branch.to_string()).or_default());
+        }
     }
 
     let dates = config.user_dates();
@@ -204,144 +1690,435 @@ fn collect_stats(reviews: &[Review], config: &Config) -> UserStatistics {
     let mut stats: UserStatistics = BTreeMap::new();
 
     for review in reviews {
-        if !review.is_within_date(
-            &dates[&review.owner.username].0,
-            &dates[&review.owner.username].1,
-        ) {
+        if !config.is_counted_review(review) {
+            continue;
+        }
+
+        let owner_dates = &dates[&review.owner.username];
+        let is_within_date =
+            review.is_within_date(&owner_dates.0, &owner_dates.1, &config.submit_label);
+        if !is_within_date && !config.approvals_by_grant_date {
             continue;
         }
 
         let repo = review.repository_name();
-        let made = review.comments_made(&users);
-        let received = review.comments_received();
-        let approvals = review.approvals(&users);
+        let branch = Some(review.branch.as_str()).filter(|_| config.by_branch);
+
+        // Approvals are counted by the owner's window, unless configured to use the window of
+        // the approver, in which case they are counted even if the change itself is not.
+        let approvals = if config.changes_only {
+            Vec::new()
+        } else {
+            review.approvals(
+                &users,
+                &config.approval_labels,
+                &config.repo_approval_min,
+                config.approval_patch_sets,
+            )
+        };
+        for (user, labels, granted_on) in approvals {
+            if config.approvals_by_grant_date
+                && !date::is_within(&dates[&user].0, &dates[&user].1, granted_on)
+            {
+                continue;
+            }
+            let paired = config.is_pair(&user, &review.owner.username);
+            update_stats(&mut stats, &user, &repo, branch, |s| {
+                s.approvals += 1;
+                s.reverted_approvals += u32::from(review.reverted);
+                for label in &labels {
+                    *s.label_approvals.entry(label.to_string()).or_default() += 1;
+                }
+                if paired {
+                    let owner = review.owner.username.clone();
+                    s.pair_reviews.entry(owner).or_default().1 += 1;
+                }
+            });
+        }
+
+        if !is_within_date {
+            continue;
+        }
+
         let patch_sets = review.patch_set_count();
+        let substantive_patch_sets = review.substantive_patch_set_count();
         let words = review.commit_message_words();
+        let revert = glob::matches(&config.revert_pattern, review.subject());
+        let upload_dates: Vec<i64> = review
+            .upload_times()
+            .filter(|time| date::is_within(&owner_dates.0, &owner_dates.1, *time))
+            .map(|time| time.div_euclid(24 * 60 * 60))
+            .collect();
+        let merged = review.is_merged(&config.submit_label);
+        let self_merged = review.is_self_merged(&config.submit_label);
+        let custom: Vec<(&str, u32)> = config
+            .custom_metrics
+            .iter()
+            .map(|metric| (metric.name.as_str(), (metric.value)(review)))
+            .collect();
 
-        let user_stats = stats
-            .entry(review.owner.username.to_string())
-            .or_insert_with(Default::default);
+        update_stats(&mut stats, &review.owner.username, &repo, branch, |s| {
+            s.changes += 1;
+            s.merged_changes += u32::from(merged);
+            s.self_merges += u32::from(self_merged);
+            s.patch_sets += patch_sets;
+            s.substantive_patch_sets += substantive_patch_sets;
+            s.commit_words += words;
+            s.reverts += u32::from(revert);
+            s.upload_dates.extend(&upload_dates);
+            for (name, value) in &custom {
+                *s.custom.entry(name.to_string()).or_default() += value;
+            }
+            s.stack_depth += review.stack_depth;
+            s.max_stack_depth = s.max_stack_depth.max(review.stack_depth);
+        });
 
-        add_stats(user_stats, "All".to_string(), received, patch_sets, words);
-        add_stats(user_stats, repo.to_string(), received, patch_sets, words);
+        // Comments aren't fetched in changes-only mode, approvals only date the change.
+        if config.changes_only {
+            continue;
+        }
+
+        let made = review.comments_made(&users);
+        let received = review.comments_received(&config.ci_accounts);
+        let received_from_others = review.comments_received_from_others(&config.ci_accounts);
+        let (resolved, unresolved, unknown) =
+            review.comments_received_by_resolution(&config.ci_accounts);
+        let (on_change, inline) = review.comments_received_on_change(&config.ci_accounts);
+        let weighted_received =
+            review.weighted_comments_received(&config.ci_accounts, config.comment_decay);
+        let ci_verifications = review.verifications(&config.ci_accounts);
+        let ping_pong =
+            review.patch_sets_after_first_review(&config.ci_accounts, &config.submit_label);
+        let self_comments = review.self_comments(config.self_comment_count);
+        let unreviewed = received == 0
+            && !(config.approved_is_reviewed
+                && review.is_approved_by_others(
+                    &config.approval_labels,
+                    &config.repo_approval_min,
+                    config.approval_patch_sets,
+                ));
+        let (threads, thread_comments) = review.comment_threads();
+        let reviewers = review.reviewers(&config.ci_accounts, &config.submit_label);
+        let turnarounds = review.author_turnarounds(&config.ci_accounts);
+        let wip_ready = review.wip_ready_transitions();
+        let open_interval = review.open_interval(&config.submit_label);
+
+        update_stats(&mut stats, &review.owner.username, &repo, branch, |s| {
+            s.unreviewed_changes += u32::from(unreviewed);
+            s.comments_received += received;
+            s.comments_received_from_others += received_from_others;
+            s.comments_received_resolved += resolved;
+            s.comments_received_unresolved += unresolved;
+            s.comments_received_unknown += unknown;
+            s.comments_received_on_change += on_change;
+            s.comments_received_inline += inline;
+            s.weighted_comments_received += weighted_received;
+            s.self_comments += self_comments;
+            s.ci_verifications += ci_verifications;
+            if let Some(ping_pong) = ping_pong {
+                s.ping_pong += ping_pong;
+                s.ping_pong_changes += 1;
+            }
+            s.wip_ready += wip_ready;
+            s.threads += threads;
+            s.reviewers.extend(reviewers.iter().cloned());
+            s.thread_comments += thread_comments;
+            s.author_turnarounds += turnarounds.len() as u32;
+            s.author_turnaround_time += turnarounds.iter().sum::<i64>() as u64;
+        });
+
+        for user in reviewers.iter().filter(|user| users.contains_key(*user)) {
+            if let Some(interval) = open_interval {
+                update_stats(&mut stats, user, &repo, branch, |s| {
+                    s.review_intervals.push(interval)
+                });
+            }
+        }
 
         for (user, comment_count) in &made {
-            let user_stats = stats
-                .entry(user.to_string())
-                .or_insert_with(Default::default);
+            let paired = config.is_pair(user, &review.owner.username);
+            update_stats(&mut stats, user, &repo, branch, |s| {
+                s.comments_made += comment_count;
+                if paired {
+                    let owner = review.owner.username.clone();
+                    s.pair_reviews.entry(owner).or_default().0 += comment_count;
+                }
+            });
+        }
 
-            let total_stats = user_stats
-                .entry("All".to_string())
-                .or_insert_with(Stats::new);
-            total_stats.comments_made += comment_count;
+        for (user, delay) in
+            review.approval_delays(&users, &config.approval_labels, &config.repo_approval_min)
+        {
+            update_stats(&mut stats, &user, &repo, branch, |s| {
+                s.approval_delays[date::delay_bucket(delay)] += 1;
+            });
+        }
 
-            let repo_stats = user_stats
-                .entry(repo.to_string())
-                .or_insert_with(Stats::new);
-            repo_stats.comments_made += comment_count;
+        for (user, vote) in review.code_review_votes(&users) {
+            update_stats(&mut stats, &user, &repo, branch, |s| {
+                s.code_review_votes[vote] += 1;
+            });
         }
 
-        for user in &approvals {
-            let user_stats = stats
-                .entry(user.to_string())
-                .or_insert_with(Default::default);
+        if let Some((user, latency)) = review.first_response(&users, &config.submit_label) {
+            update_stats(&mut stats, &user, &repo, branch, |s| {
+                s.first_responses += 1;
+                s.first_response_time += latency.max(0) as u64;
+            });
+        }
 
-            let total_stats = user_stats
-                .entry("All".to_string())
-                .or_insert_with(Stats::new);
-            total_stats.approvals += 1;
+        for (user, time) in review.activity() {
+            if !users.contains_key(user) || !date::is_within(&dates[user].0, &dates[user].1, time) {
+                continue;
+            }
+            let day = time.div_euclid(24 * 60 * 60);
+            update_stats(&mut stats, user, &repo, branch, |s| {
+                s.active_dates.insert(day);
+            });
+        }
+    }
 
-            let repo_stats = user_stats
-                .entry(repo.to_string())
-                .or_insert_with(Stats::new);
-            repo_stats.approvals += 1;
+    for repos in stats.values_mut() {
+        let mut breadth = 0;
+        for (name, repo) in repos.iter_mut() {
+            repo.active_days = repo.active_dates.len() as u32;
+            repo.upload_days = repo.upload_dates.len() as u32;
+            repo.distinct_reviewers = repo.reviewers.len() as u32;
+            repo.peak_reviews = review::peak_overlap(&repo.review_intervals);
+            for branch in repo.branches.values_mut() {
+                branch.peak_reviews = review::peak_overlap(&branch.review_intervals);
+                branch.active_days = branch.active_dates.len() as u32;
+                branch.upload_days = branch.upload_dates.len() as u32;
+                branch.distinct_reviewers = branch.reviewers.len() as u32;
+            }
+            if name != "All" && (repo.comments_made > 0 || repo.approvals > 0) {
+                repo.repo_breadth = 1;
+                breadth += 1;
+            }
+        }
+        if let Some(all) = repos.get_mut("All") {
+            all.repo_breadth = breadth;
         }
     }
 
     stats
 }
 
-fn get_average_stats(stats: &UserStatistics) -> Stats {
-    let mut avg_stats = Stats::new();
+/// Sums the given repo rows over all users that have one, "All" sums over every user.
+fn get_total_stats(stats: &UserStatistics, repo_name: &str) -> Stats {
+    let mut total_stats = Stats::new();
 
-    for repos in stats.values() {
-        let repo = repos.get("All").expect("Failed to get 'All' row");
-        avg_stats.changes += repo.changes;
-        avg_stats.approvals += repo.approvals;
-        avg_stats.comments_made += repo.comments_made;
-        avg_stats.comments_received += repo.comments_received;
-        avg_stats.commit_words += repo.commit_words;
-        avg_stats.patch_sets += repo.patch_sets;
+    for repo in stats.values().filter_map(|repos| repos.get(repo_name)) {
+        total_stats.contributors += 1;
+        total_stats.changes += repo.changes;
+        total_stats.merged_changes += repo.merged_changes;
+        total_stats.unreviewed_changes += repo.unreviewed_changes;
+        total_stats.approvals += repo.approvals;
+        total_stats.comments_made += repo.comments_made;
+        total_stats.comments_received += repo.comments_received;
+        total_stats.comments_received_from_others += repo.comments_received_from_others;
+        total_stats.comments_received_resolved += repo.comments_received_resolved;
+        total_stats.comments_received_unresolved += repo.comments_received_unresolved;
+        total_stats.comments_received_unknown += repo.comments_received_unknown;
+        total_stats.comments_received_on_change += repo.comments_received_on_change;
+        total_stats.comments_received_inline += repo.comments_received_inline;
+        total_stats.weighted_comments_received += repo.weighted_comments_received;
+        total_stats.commit_words += repo.commit_words;
+        total_stats.patch_sets += repo.patch_sets;
+        total_stats.substantive_patch_sets += repo.substantive_patch_sets;
+        total_stats.first_responses += repo.first_responses;
+        total_stats.first_response_time += repo.first_response_time;
+        total_stats.author_turnarounds += repo.author_turnarounds;
+        total_stats.author_turnaround_time += repo.author_turnaround_time;
+        total_stats.self_comments += repo.self_comments;
+        total_stats.stack_depth += repo.stack_depth;
+        total_stats.ci_verifications += repo.ci_verifications;
+        total_stats.ping_pong += repo.ping_pong;
+        total_stats.ping_pong_changes += repo.ping_pong_changes;
+        total_stats.self_merges += repo.self_merges;
+        total_stats.reverts += repo.reverts;
+        total_stats.reverted_approvals += repo.reverted_approvals;
+        total_stats.wip_ready += repo.wip_ready;
+        total_stats.threads += repo.threads;
+        total_stats.thread_comments += repo.thread_comments;
+        total_stats.active_days += repo.active_days;
+        total_stats.upload_days += repo.upload_days;
+        total_stats.distinct_reviewers += repo.distinct_reviewers;
+        total_stats.repo_breadth += repo.repo_breadth;
+        for (label, approvals) in &repo.label_approvals {
+            *total_stats
+                .label_approvals
+                .entry(label.clone())
+                .or_default() += approvals;
+        }
+        for (name, value) in &repo.custom {
+            *total_stats.custom.entry(name.clone()).or_default() += value;
+        }
+        for (total, delays) in total_stats
+            .approval_delays
+            .iter_mut()
+            .zip(&repo.approval_delays)
+        {
+            *total += delays;
+        }
+        for (total, votes) in total_stats
+            .code_review_votes
+            .iter_mut()
+            .zip(&repo.code_review_votes)
+        {
+            *total += votes;
+        }
+        total_stats.max_stack_depth = total_stats.max_stack_depth.max(repo.max_stack_depth);
+        total_stats.peak_reviews = total_stats.peak_reviews.max(repo.peak_reviews);
     }
 
-    let count = stats.len() as u32;
+    total_stats
+}
+
+/// Averages the given repo rows over all users that have one, "All" averages over every user.
+fn get_average_stats(stats: &UserStatistics, repo_name: &str) -> Stats {
+    let mut avg_stats = get_total_stats(stats, repo_name);
+
+    let count = avg_stats.contributors;
     avg_stats.changes /= count;
+    avg_stats.merged_changes /= count;
+    avg_stats.unreviewed_changes /= count;
     avg_stats.approvals /= count;
     avg_stats.comments_made /= count;
     avg_stats.comments_received /= count;
+    avg_stats.comments_received_from_others /= count;
+    avg_stats.comments_received_resolved /= count;
+    avg_stats.comments_received_unresolved /= count;
+    avg_stats.comments_received_unknown /= count;
+    avg_stats.comments_received_on_change /= count;
+    avg_stats.comments_received_inline /= count;
+    avg_stats.weighted_comments_received /= f64::from(count);
     avg_stats.commit_words /= count;
     avg_stats.patch_sets /= count;
+    avg_stats.substantive_patch_sets /= count;
+    avg_stats.first_responses /= count;
+    avg_stats.first_response_time /= u64::from(count);
+    avg_stats.author_turnarounds /= count;
+    avg_stats.author_turnaround_time /= u64::from(count);
+    avg_stats.self_comments /= count;
+    avg_stats.stack_depth /= count;
+    avg_stats.ci_verifications /= count;
+    avg_stats.ping_pong /= count;
+    avg_stats.ping_pong_changes /= count;
+    avg_stats.self_merges /= count;
+    avg_stats.reverts /= count;
+    avg_stats.reverted_approvals /= count;
+    avg_stats.wip_ready /= count;
+    avg_stats.threads /= count;
+    avg_stats.thread_comments /= count;
+    avg_stats.active_days /= count;
+    avg_stats.upload_days /= count;
+    avg_stats.distinct_reviewers /= count;
+    avg_stats.repo_breadth /= count;
+    for approvals in avg_stats.label_approvals.values_mut() {
+        *approvals /= count;
+    }
+    for value in avg_stats.custom.values_mut() {
+        *value /= count;
+    }
+    for delays in avg_stats.approval_delays.iter_mut() {
+        *delays /= count;
+    }
+    for votes in avg_stats.code_review_votes.iter_mut() {
+        *votes /= count;
+    }
 
     avg_stats
 }
 
-fn new_csv_writer(filepath: &str) -> csv::Writer<std::fs::File> {
-    let mut writer = csv::Writer::from_path(filepath).expect("Failed to create csv writer");
-
-    writer
-        .write_record(&[
-            "User", "Repo", "CH", "AP", "CM", "CR", "CR/CH", "CW", "CW/CH", "PS", "PS/CH",
-        ])
-        .expect("Failed to create header record");
-
-    writer
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
-fn write_record(writer: &mut csv::Writer<std::fs::File>, user: &str, repo: &str, stats: &Stats) {
-    writer
-        .write_record(&[
-            &user.to_string(),
-            &repo.to_string(),
-            &stats.changes.to_string(),
-            &stats.approvals.to_string(),
-            &stats.comments_made.to_string(),
-            &stats.comments_received.to_string(),
-            &(stats.comments_received as f32 / stats.changes as f32).to_string(),
-            &stats.commit_words.to_string(),
-            &(stats.commit_words as f32 / stats.changes as f32).to_string(),
-            &stats.patch_sets.to_string(),
-            &(stats.patch_sets as f32 / stats.changes as f32).to_string(),
-        ])
-        .expect("Failed to write record to csv file");
-}
+    fn config(extra: &str) -> Config {
+        let config = format!(
+            "server = \"gerrit\"\nport = \"29418\"\nfrom = 2019-01-01\nto = 2019-12-31\n{}\n\n[[user]]\nusername = \"jado\"\nfullname = \"Jane Doe\"\n",
+            extra
+        );
+        let mut config: Config = toml::from_str(&config).expect("Failed to parse config");
+        config.fill_missing_dates();
+        config
+    }
 
-fn write_simple_stats(stats: &UserStatistics, config: &Config) {
-    let mut writer = new_csv_writer("stats.csv");
+    fn change_row(number: i32, owner: &str, uploader: &str) -> String {
+        format!(
+            r#"{{"project":"repo","branch":"master","id":"I{0}","number":{0},"owner":{{"name":"{1}","username":"{1}"}},"commitMessage":"Fix","createdOn":1561939200,"comments":[],"patchSets":[{{"createdOn":1561939200,"uploader":{{"name":"{2}","username":"{2}"}},"approvals":[{{"type":"SUBM","value":"1","grantedOn":1562025600,"by":{{"username":"{2}"}}}}]}}]}}"#,
+            number, owner, uploader
+        )
+    }
 
-    let avg_stats = get_average_stats(&stats);
-    write_record(&mut writer, "Average", "All", &avg_stats);
+    /// Reads the input from a file like `--input` and returns the changes counted and who for.
+    fn counted_changes(input: &str, config: &Config) -> Vec<(i32, String)> {
+        static FILES: AtomicUsize = AtomicUsize::new(0);
+        let filepath = std::env::temp_dir()
+            .join(format!(
+                "gerrit-stats-input-{}-{}.json",
+                std::process::id(),
+                FILES.fetch_add(1, Ordering::Relaxed)
+            ))
+            .display()
+            .to_string();
+        std::fs::write(&filepath, input).expect("Failed to write input file");
+        let mut reviews = input::read_reviews(&filepath, false, 1);
+        std::fs::remove_file(&filepath).expect("Failed to remove input file");
 
-    let users = config.user_names();
+        prepare_reviews(&mut reviews, config);
+        let stats = collect_period_stats(&reviews, config);
+        assert_eq!(stats["All"]["jado"]["All"].changes, reviews.len() as u32);
+        reviews
+            .iter()
+            .map(|review| (review.number, review.owner.username.clone()))
+            .collect()
+    }
 
-    for (user, repos) in stats {
-        let stats = repos.get("All").expect("Failed to get 'All' row");
-        let user_name = &users[user];
-        write_record(&mut writer, user_name, "All", &stats);
+    #[test]
+    fn input_with_unconfigured_owner() {
+        let input = format!(
+            "{}\n{}\n",
+            change_row(1, "jado", "jado"),
+            change_row(2, "carol", "carol")
+        );
+        let changes = counted_changes(&input, &config(""));
+        assert_eq!(changes, vec![(1, "jado".to_string())]);
     }
 
-    writer.flush().expect("Failed to flush writer");
-}
+    #[test]
+    fn attribute_by_unconfigured_uploader() {
+        let input = format!(
+            "{}\n{}\n{}\n",
+            change_row(1, "carol", "jado"),
+            change_row(2, "jado", "carol"),
+            change_row(3, "jado", "jado")
+        );
 
-fn write_detailed_stats(stats: &UserStatistics, config: &Config) {
-    let mut writer = new_csv_writer("detailed.csv");
-    let users = config.user_names();
+        let changes = counted_changes(&input, &config("attribute_by = \"uploader\""));
+        let expected = vec![(1, "jado".to_string()), (3, "jado".to_string())];
+        assert_eq!(changes, expected);
 
-    for (user, repos) in stats {
-        let user_name = &users[user];
-        for (repo, stats) in repos {
-            write_record(&mut writer, user_name, repo, stats);
-        }
+        let changes = counted_changes(&input, &config("attribute_by = \"both\""));
+        let expected: Vec<(i32, String)> =
+            (1..=3).map(|number| (number, "jado".to_string())).collect();
+        assert_eq!(changes, expected);
     }
 
-    writer.flush().expect("Failed to flush writer");
+    #[test]
+    fn interpolate_env_skips_comments() {
+        std::env::set_var("GERRIT_STATS_TEST_HOST", "gerrit.example.com");
+        let mut value: toml::Value = toml::from_str(
+            "# ${GERRIT_STATS_TEST_UNSET}\nserver = \"${GERRIT_STATS_TEST_HOST}\" # ${GERRIT_STATS_TEST_UNSET}\n",
+        )
+        .unwrap();
+        interpolate_env(&mut value).unwrap();
+        assert_eq!(value["server"].as_str(), Some("gerrit.example.com"));
+
+        let mut value: toml::Value =
+            toml::from_str("server = \"${GERRIT_STATS_TEST_UNSET}\"\n").unwrap();
+        assert!(interpolate_env(&mut value).is_err());
+    }
 }
@@ -0,0 +1,1718 @@
+use chrono::NaiveDateTime;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{self, Write};
+use std::rc::Rc;
+use std::str::{self, FromStr};
+
+use crate::date::DELAY_BUCKETS;
+use crate::encoding::Encoding;
+use crate::glob;
+use crate::review::{Review, CODE_REVIEW_VOTES};
+use crate::{get_average_stats, get_total_stats, AverageRow, Config, PeriodStatistics, Stats};
+
+/// A single output column, the `value` function formats the cell for a given row. Columns holding
+/// a per-user metric also have a name it's exported under, e.g. to Prometheus. Ratio columns are
+/// derived from the other ones and left out with `--raw`.
+pub struct Column {
+    pub name: String,
+    metric: Option<String>,
+    ratio: bool,
+    value: Box<dyn Fn(&Row) -> String>,
+}
+
+struct Row<'a> {
+    period: &'a str,
+    user: &'a str,
+    repo: &'a str,
+    branch: &'a str,
+    stats: &'a Stats,
+}
+
+fn column(name: &str, metric: Option<&'static str>, value: fn(&Row) -> String) -> Column {
+    Column {
+        name: name.to_string(),
+        metric: metric.map(str::to_string),
+        ratio: false,
+        value: Box::new(value),
+    }
+}
+
+fn ratio(name: &str, metric: Option<&'static str>, value: fn(&Row) -> f32) -> Column {
+    Column {
+        name: name.to_string(),
+        metric: metric.map(str::to_string),
+        ratio: true,
+        value: Box::new(move |row| ratio_cell(value(row))),
+    }
+}
+
+/// Formats a ratio or an average. Without anything to divide by, e.g. comments per change of a
+/// user without changes, the cell is left empty instead of writing NaN or inf.
+fn ratio_cell(value: f32) -> String {
+    if value.is_finite() {
+        value.to_string()
+    } else {
+        String::new()
+    }
+}
+
+/// Columns that are filled in with `--changes-only`, the others are left blank.
+const CHANGE_COLUMNS: &[&str] = &[
+    "Period",
+    "User",
+    "Repo",
+    "Branch",
+    "CH",
+    "MCH",
+    "CW",
+    "CW/CH",
+    "PS",
+    "PS/CH",
+    "SPS",
+    "SPS/CH",
+    "SD/CH",
+    "SDM",
+    "SM",
+    "SM/CH",
+    "RV",
+    "RV/CH",
+    "UploadDays",
+    "US",
+];
+
+/// Returns all available columns, in the default output order.
+fn all_columns(config: &Config) -> Vec<Column> {
+    let mut columns = metric_columns(config);
+    if config.score {
+        columns.push(score_column(config));
+    }
+    columns
+}
+
+/// Returns the Score column, the sum of the `score_weights` columns multiplied by their weights.
+/// Cells that aren't numbers, e.g. ratios of users without changes, count as 0.
+fn score_column(config: &Config) -> Column {
+    let mut columns = metric_columns(config);
+    let parts: Vec<(Column, f64)> = config
+        .score_weights
+        .iter()
+        .filter_map(|(name, weight)| {
+            let index = columns.iter().position(|column| &column.name == name)?;
+            Some((columns.swap_remove(index), *weight))
+        })
+        .collect();
+
+    Column {
+        name: "Score".to_string(),
+        metric: Some("score".to_string()),
+        ratio: false,
+        value: Box::new(move |row| {
+            let score: f64 = parts
+                .iter()
+                .map(|(column, weight)| {
+                    let value: f64 = (column.value)(row).parse().unwrap_or_default();
+                    if value.is_finite() {
+                        value * weight
+                    } else {
+                        0.0
+                    }
+                })
+                .sum();
+            (score as f32).to_string()
+        }),
+    }
+}
+
+/// Checks that the `score_weights` are given for existing columns.
+pub fn validate_score(config: &Config) -> Result<(), String> {
+    if config.score_weights.is_empty() {
+        return Err("no score_weights are configured".to_string());
+    }
+
+    let columns = metric_columns(config);
+    for name in config.score_weights.keys() {
+        if !columns.iter().any(|column| &column.name == name) {
+            return Err(format!("unknown column '{}' in score_weights", name));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the columns of the collected statistics, without the derived Score.
+fn metric_columns(config: &Config) -> Vec<Column> {
+    let mut columns = vec![
+        column("Period", None, |row| row.period.to_string()),
+        column("User", None, |row| row.user.to_string()),
+        column("Repo", None, |row| row.repo.to_string()),
+        column("Branch", None, |row| row.branch.to_string()),
+        column("CH", Some("changes"), |row| row.stats.changes.to_string()),
+        column("MCH", Some("merged_changes"), |row| {
+            row.stats.merged_changes.to_string()
+        }),
+        column("UCH", Some("unreviewed_changes"), |row| {
+            row.stats.unreviewed_changes.to_string()
+        }),
+        ratio("UCH/CH", None, |row| {
+            row.stats.unreviewed_changes as f32 / row.stats.changes as f32
+        }),
+        column("AP", Some("approvals"), |row| {
+            row.stats.approvals.to_string()
+        }),
+    ];
+
+    // Approvals are broken down per label when more than one label counts as approval.
+    if config.approval_labels.len() > 1 {
+        for label in &config.approval_labels {
+            let name = label.name.clone();
+            columns.push(Column {
+                name: format!("AP({})", label.name),
+                metric: None,
+                ratio: false,
+                value: Box::new(move |row| {
+                    let approvals = row.stats.label_approvals.get(&name);
+                    approvals.cloned().unwrap_or_default().to_string()
+                }),
+            });
+        }
+    }
+
+    columns.extend(vec![
+        column("CM", Some("comments_made"), |row| {
+            row.stats.comments_made.to_string()
+        }),
+        column("CR", Some("comments_received"), |row| {
+            row.stats.comments_received.to_string()
+        }),
+        column("CRR", Some("comments_received_resolved"), |row| {
+            row.stats.comments_received_resolved.to_string()
+        }),
+        column("CRU", Some("comments_received_unresolved"), |row| {
+            row.stats.comments_received_unresolved.to_string()
+        }),
+        column("CRN", Some("comments_received_unknown"), |row| {
+            row.stats.comments_received_unknown.to_string()
+        }),
+        column("CRA", Some("comments_received_on_change"), |row| {
+            row.stats.comments_received_on_change.to_string()
+        }),
+        column("CRI", Some("comments_received_inline"), |row| {
+            row.stats.comments_received_inline.to_string()
+        }),
+        ratio("CRA/CRI", None, |row| {
+            row.stats.comments_received_on_change as f32 / row.stats.comments_received_inline as f32
+        }),
+        column("CRW", Some("weighted_comments_received"), |row| {
+            row.stats.weighted_comments_received.to_string()
+        }),
+        ratio("CR/CH", None, |row| {
+            row.stats.comments_received as f32 / row.stats.changes as f32
+        }),
+        column("CW", Some("commit_words"), |row| {
+            row.stats.commit_words.to_string()
+        }),
+        ratio("CW/CH", None, |row| {
+            row.stats.commit_words as f32 / row.stats.changes as f32
+        }),
+        column("PS", Some("patch_sets"), |row| {
+            row.stats.patch_sets.to_string()
+        }),
+        ratio("PS/CH", None, |row| {
+            row.stats.patch_sets as f32 / row.stats.changes as f32
+        }),
+        column("SPS", Some("substantive_patch_sets"), |row| {
+            row.stats.substantive_patch_sets.to_string()
+        }),
+        ratio("SPS/CH", None, |row| {
+            row.stats.substantive_patch_sets as f32 / row.stats.changes as f32
+        }),
+        column("FR", Some("first_responses"), |row| {
+            row.stats.first_responses.to_string()
+        }),
+        ratio("FRT", Some("first_response_time_seconds"), |row| {
+            row.stats.first_response_time as f32 / row.stats.first_responses as f32
+        }),
+        ratio("AT", Some("author_turnaround_seconds"), |row| {
+            row.stats.author_turnaround_time as f32 / row.stats.author_turnarounds as f32
+        }),
+        column("SC", Some("self_comments"), |row| {
+            row.stats.self_comments.to_string()
+        }),
+        ratio("SD/CH", None, |row| {
+            row.stats.stack_depth as f32 / row.stats.changes as f32
+        }),
+        column("SDM", Some("max_stack_depth"), |row| {
+            row.stats.max_stack_depth.to_string()
+        }),
+        column("CIV", Some("ci_verifications"), |row| {
+            row.stats.ci_verifications.to_string()
+        }),
+        ratio("PP", None, |row| {
+            row.stats.ping_pong as f32 / row.stats.ping_pong_changes as f32
+        }),
+        ratio("TL", None, |row| {
+            row.stats.thread_comments as f32 / row.stats.threads as f32
+        }),
+        column("SM", Some("self_merges"), |row| {
+            row.stats.self_merges.to_string()
+        }),
+        ratio("SM/CH", None, |row| {
+            row.stats.self_merges as f32 / row.stats.changes as f32
+        }),
+        column("RV", Some("reverts"), |row| row.stats.reverts.to_string()),
+        ratio("RV/CH", None, |row| {
+            row.stats.reverts as f32 / row.stats.changes as f32
+        }),
+        column("RAP", Some("reverted_approvals"), |row| {
+            row.stats.reverted_approvals.to_string()
+        }),
+        ratio("RAP/AP", None, |row| {
+            row.stats.reverted_approvals as f32 / row.stats.approvals as f32
+        }),
+        column("WR", Some("wip_ready_transitions"), |row| {
+            row.stats.wip_ready.to_string()
+        }),
+        column("DR", Some("distinct_reviewers"), |row| {
+            row.stats.distinct_reviewers.to_string()
+        }),
+        column("PCR", Some("peak_concurrent_reviews"), |row| {
+            row.stats.peak_reviews.to_string()
+        }),
+        column("RepoBreadth", Some("repo_breadth"), |row| {
+            row.stats.repo_breadth.to_string()
+        }),
+        column("AD", Some("active_days"), |row| {
+            row.stats.active_days.to_string()
+        }),
+        column("UploadDays", Some("upload_days"), |row| {
+            row.stats.upload_days.to_string()
+        }),
+        column("US", None, |row| match row.stats.contributors {
+            0 => String::new(),
+            contributors => contributors.to_string(),
+        }),
+    ]);
+
+    // Comments from others only get their own column when asked for, next to CR.
+    if config.exclude_own_comments {
+        let index = columns
+            .iter()
+            .position(|column| column.name == "CR")
+            .expect("Failed to find CR column");
+        columns.insert(
+            index + 1,
+            column("CRO", Some("comments_received_from_others"), |row| {
+                row.stats.comments_received_from_others.to_string()
+            }),
+        );
+    }
+
+    for metric in &config.custom_metrics {
+        let name = metric.name.clone();
+        columns.push(Column {
+            name: name.clone(),
+            metric: Some(name.clone()),
+            ratio: false,
+            value: Box::new(move |row| {
+                let value = row.stats.custom.get(&name);
+                value.cloned().unwrap_or_default().to_string()
+            }),
+        });
+    }
+
+    // Count columns are also reported per active day when normalizing.
+    if let Some(Normalize::ActiveDays) = config.normalize_by {
+        type Count = fn(&Stats) -> u32;
+        let counts: Vec<(&str, Count)> = vec![
+            ("CH", |stats| stats.changes),
+            ("AP", |stats| stats.approvals),
+            ("CM", |stats| stats.comments_made),
+            ("CR", |stats| stats.comments_received),
+            ("PS", |stats| stats.patch_sets),
+            ("FR", |stats| stats.first_responses),
+            ("SC", |stats| stats.self_comments),
+            ("SM", |stats| stats.self_merges),
+        ];
+        for (name, count) in counts {
+            columns.push(Column {
+                name: format!("{}/AD", name),
+                metric: None,
+                ratio: true,
+                value: Box::new(move |row| {
+                    ratio_cell(count(row.stats) as f32 / row.stats.active_days as f32)
+                }),
+            });
+        }
+    }
+
+    // Everything else depends on comments and approvals, which aren't fetched in changes-only mode.
+    if config.changes_only {
+        for column in columns.iter_mut().filter(|column| {
+            !CHANGE_COLUMNS.contains(&column.name.as_str())
+                && !config
+                    .custom_metrics
+                    .iter()
+                    .any(|metric| metric.name == column.name)
+        }) {
+            column.metric = None;
+            column.value = Box::new(|_| String::new());
+        }
+    }
+
+    columns
+}
+
+/// What count columns are normalized by with `--normalize-by`.
+#[derive(Debug, Clone, Copy)]
+pub enum Normalize {
+    /// Distinct days the user uploaded, commented on or voted on a change.
+    ActiveDays,
+}
+
+impl Normalize {
+    pub const NAMES: &'static [&'static str] = &["active-days"];
+}
+
+impl FromStr for Normalize {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "active-days" => Ok(Normalize::ActiveDays),
+            _ => Err(format!("invalid normalization '{}'", value)),
+        }
+    }
+}
+
+fn find_column(name: &str, config: &Config) -> Result<Column, String> {
+    let mut columns = all_columns(config);
+    match columns.iter().position(|column| column.name == name) {
+        Some(index) => Ok(columns.swap_remove(index)),
+        None => {
+            let known: Vec<&str> = columns.iter().map(|column| column.name.as_str()).collect();
+            Err(format!(
+                "unknown column '{}', expected one of: {}",
+                name,
+                known.join(",")
+            ))
+        }
+    }
+}
+
+pub fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+pub fn validate_columns(config: &Config) -> Result<(), String> {
+    for name in config.columns.iter().chain(config.column_labels.keys()) {
+        find_column(name, config)?;
+    }
+    for name in &config.columns {
+        if config.raw && find_column(name, config)?.ratio {
+            return Err(format!(
+                "ratio column '{}' can't be selected with --raw",
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Returns names of all exported metrics.
+pub fn metric_names(config: &Config) -> Vec<String> {
+    all_columns(config)
+        .into_iter()
+        .filter_map(|column| column.metric)
+        .collect()
+}
+
+/// Returns exported metric names with their values for the given stats.
+pub fn metrics(stats: &Stats, config: &Config) -> Vec<(String, String)> {
+    let row = Row {
+        period: "",
+        user: "",
+        repo: "",
+        branch: "",
+        stats,
+    };
+
+    all_columns(config)
+        .into_iter()
+        .filter_map(|column| Some((column.metric?, (column.value)(&row))))
+        .collect()
+}
+
+/// Column that rows are sorted by, parsed from `<column>[:asc|desc]`.
+#[derive(Debug)]
+pub struct SortBy {
+    column: String,
+    descending: bool,
+}
+
+pub fn parse_sort_by(value: &str, config: &Config) -> Result<SortBy, String> {
+    let mut parts = value.splitn(2, ':');
+    let name = parts.next().unwrap_or_default();
+    let descending = match parts.next() {
+        None | Some("desc") => true,
+        Some("asc") => false,
+        Some(order) => {
+            return Err(format!(
+                "invalid sort order '{}', expected asc or desc",
+                order
+            ))
+        }
+    };
+
+    Ok(SortBy {
+        column: find_column(name, config)?.name,
+        descending,
+    })
+}
+
+/// Returns the column to pivot by, given by name like in `--columns`.
+pub fn parse_pivot(value: &str, config: &Config) -> Result<String, String> {
+    Ok(find_column(value, config)?.name)
+}
+
+/// Sorts rows by the configured column, numerically when both values are numbers.
+fn sort_rows(rows: &mut [Row], config: &Config) {
+    let sort_by = match &config.sort_by {
+        Some(sort_by) => sort_by,
+        None => return,
+    };
+    let column = find_column(&sort_by.column, config).expect("Failed to find column");
+
+    rows.sort_by(|a, b| {
+        let (a, b) = ((column.value)(a), (column.value)(b));
+        let ordering = match (a.parse::<f64>(), b.parse::<f64>()) {
+            (Ok(a), Ok(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+            _ => a.cmp(&b),
+        };
+
+        if sort_by.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    });
+}
+
+/// Returns columns selected in the config, or all of them if none were selected. The Period and
+/// Branch columns are only included by default when changes are bucketed or broken down by branch.
+fn selected_columns(config: &Config) -> Vec<Column> {
+    if config.columns.is_empty() {
+        return all_columns(config)
+            .into_iter()
+            .filter(|column| column.name != "Period" || config.bucket.is_some())
+            .filter(|column| column.name != "Branch" || config.by_branch)
+            .filter(|column| !column.ratio || !config.raw)
+            .collect();
+    }
+
+    config
+        .columns
+        .iter()
+        .map(|name| find_column(name, config).expect("Failed to find column"))
+        .collect()
+}
+
+/// Returns the usernames shown in messages and the explain file by username, which are pseudonyms
+/// when anonymizing like the names in the CSV files.
+pub fn shown_usernames(config: &Config) -> BTreeMap<String, String> {
+    if config.anonymize {
+        config.user_pseudonyms()
+    } else {
+        let usernames = config.user.iter().map(|user| &user.username);
+        usernames
+            .map(|username| (username.clone(), username.clone()))
+            .collect()
+    }
+}
+
+/// Returns names shown in the output, which are pseudonyms when anonymizing.
+pub fn display_names(config: &Config) -> BTreeMap<String, String> {
+    if config.anonymize {
+        config.user_pseudonyms().into_iter().collect()
+    } else {
+        config.user_names()
+    }
+}
+
+/// Returns the prefix stripped from repo names in the output. With `auto` it's the longest common
+/// prefix of path segments of all repos, so the last segment is always kept.
+fn repo_prefix(stats: &PeriodStatistics, config: &Config) -> String {
+    let prefix = match &config.project_prefix_strip {
+        Some(prefix) => prefix,
+        None => return String::new(),
+    };
+    if prefix != "auto" {
+        return prefix.to_string();
+    }
+
+    let mut repos = stats
+        .values()
+        .flat_map(|stats| stats.values())
+        .flat_map(|repos| repos.keys())
+        .filter(|repo| *repo != "All");
+    let first = match repos.next() {
+        Some(first) => first,
+        None => return String::new(),
+    };
+
+    let segment_end = |len: usize| first[..len].rfind('/').map_or(0, |index| index + 1);
+    let mut len = segment_end(first.len());
+    for repo in repos {
+        while len > 0 && !(repo.len() > len && repo.starts_with(&first[..len])) {
+            len = segment_end(len - 1);
+        }
+    }
+
+    first[..len].to_string()
+}
+
+/// Returns the repo name shown in the output, the "All" row and names that would end up empty are
+/// left as they are.
+fn strip_repo<'a>(repo: &'a str, prefix: &str) -> &'a str {
+    match repo.strip_prefix(prefix) {
+        Some(stripped) if repo != "All" && !stripped.is_empty() => stripped,
+        _ => repo,
+    }
+}
+
+/// Replaces the characters of a `--label` that aren't safe in file names, e.g. `/` or spaces,
+/// with `_`.
+pub fn sanitize_label(label: &str) -> Result<String, String> {
+    let label: String = label
+        .trim()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    if label.is_empty() {
+        return Err("the label is empty".to_string());
+    }
+    Ok(label)
+}
+
+/// Returns the path of an output file with the prefix and the label, e.g. `stats-2024Q1.csv`.
+pub fn output_path(name: &str, extension: &str, config: &Config) -> String {
+    match &config.label {
+        Some(label) => format!("{}{}-{}.{}", config.output_prefix, name, label, extension),
+        None => format!("{}{}.{}", config.output_prefix, name, extension),
+    }
+}
+
+/// Returns the path of a CSV output file, e.g. `stats.csv`.
+pub fn output_file(name: &str, config: &Config) -> String {
+    output_path(name, "csv", config)
+}
+
+/// Output file, transcoding the written text from UTF-8 to the output encoding.
+#[derive(Debug)]
+struct Output {
+    path: String,
+    /// File the output is written to until it's finished, then it replaces `path`.
+    temp_path: Option<String>,
+    file: File,
+    encoding: Encoding,
+    lossy: bool,
+    /// UTF-8 bytes of a character split between two writes.
+    pending: Vec<u8>,
+}
+
+impl Output {
+    /// Creates the output file. It's written to a temporary file in the same directory and
+    /// renamed over the target when finished, so readers and other instances writing the same
+    /// file never see a partial one.
+    fn create(filepath: &str, config: &Config) -> Self {
+        let temp_path = format!("{}.{}.tmp", filepath, std::process::id());
+        let file = File::create(&temp_path).expect("Failed to create output file");
+        let mut output = Output::new(filepath, file, config);
+        output.temp_path = Some(temp_path);
+        output
+    }
+
+    fn new(filepath: &str, file: File, config: &Config) -> Self {
+        Output {
+            path: filepath.to_string(),
+            temp_path: None,
+            file,
+            encoding: config.encoding,
+            lossy: config.lossy,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Syncs the file, so it isn't truncated after a crash, then moves the temporary file into
+    /// place.
+    fn finish(mut self) {
+        self.file.sync_all().expect("Failed to sync output file");
+        if let Some(temp_path) = self.temp_path.take() {
+            std::fs::rename(temp_path, &self.path).expect("Failed to move output file into place");
+        }
+    }
+}
+
+impl Drop for Output {
+    /// Removes the temporary file of an output that failed before it was finished.
+    fn drop(&mut self) {
+        if let Some(temp_path) = self.temp_path.take() {
+            let _ = std::fs::remove_file(temp_path);
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.encoding == Encoding::Utf8 {
+            return self.file.write(buf);
+        }
+
+        self.pending.extend_from_slice(buf);
+        let complete = match str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(err) if err.error_len().is_none() => err.valid_up_to(),
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+
+        let text = str::from_utf8(&self.pending[..complete]).expect("Failed to decode output");
+        let bytes = match self.encoding.encode(text, self.lossy) {
+            Ok(bytes) => bytes,
+            Err(c) => {
+                let message = format!(
+                    "'{}' can't be represented in {} in {}, pass --lossy to replace it with '?'",
+                    c, self.encoding, self.path
+                );
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+        };
+        self.file.write_all(&bytes)?;
+        self.pending.drain(..complete);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+struct Writer {
+    writer: csv::Writer<Output>,
+    columns: Vec<Column>,
+}
+
+fn header(columns: &[Column], config: &Config) -> Vec<String> {
+    columns
+        .iter()
+        .map(|column| {
+            let label = config.column_labels.get(&column.name);
+            label.unwrap_or(&column.name).to_string()
+        })
+        .collect()
+}
+
+fn new_csv_writer(filepath: &str, columns: Vec<Column>, config: &Config) -> Writer {
+    let mut writer = csv::Writer::from_writer(Output::create(filepath, config));
+
+    writer
+        .write_record(header(&columns, config))
+        .expect("Failed to create header record");
+
+    Writer { writer, columns }
+}
+
+fn finish(writer: Writer) {
+    finish_csv(writer.writer);
+}
+
+fn finish_csv(writer: csv::Writer<Output>) {
+    let output = writer.into_inner().expect("Failed to flush writer");
+    output.finish();
+}
+
+fn format_row(columns: &[Column], row: &Row) -> Vec<String> {
+    columns.iter().map(|column| (column.value)(row)).collect()
+}
+
+fn write_row(writer: &mut Writer, row: &Row) {
+    writer
+        .writer
+        .write_record(format_row(&writer.columns, row))
+        .expect("Failed to write record to csv file");
+}
+
+fn format_record(
+    columns: &[Column],
+    period: &str,
+    user: &str,
+    repo: &str,
+    stats: &Stats,
+) -> Vec<String> {
+    let row = Row {
+        period,
+        user,
+        repo,
+        branch: "All",
+        stats,
+    };
+    format_row(columns, &row)
+}
+
+fn write_record(writer: &mut Writer, period: &str, user: &str, repo: &str, stats: &Stats) {
+    let record = format_record(&writer.columns, period, user, repo, stats);
+    writer
+        .writer
+        .write_record(record)
+        .expect("Failed to write record to csv file");
+}
+
+pub fn write_simple_stats(stats: &PeriodStatistics, config: &Config) {
+    let mut writer = new_csv_writer(
+        &output_file("stats", config),
+        simple_columns(config),
+        config,
+    );
+    write_simple_rows(&mut writer, stats, config);
+    finish(writer);
+}
+
+/// Simple statistics of a previous run read back for `--baseline`, the numeric cells of every row
+/// by the column header, keyed by period and user.
+#[derive(Debug)]
+pub struct Baseline {
+    columns: BTreeSet<String>,
+    has_period: bool,
+    rows: BTreeMap<(String, String), BTreeMap<String, f32>>,
+}
+
+impl Baseline {
+    fn value(&self, period: &str, user: &str, column: &str) -> Option<f32> {
+        let period = if self.has_period { period } else { "" };
+        let row = self.rows.get(&(period.to_string(), user.to_string()))?;
+        row.get(column).copied()
+    }
+
+    /// Returns the users with a row in the baseline, e.g. to find the ones missing from a run.
+    pub fn users(&self) -> BTreeSet<&str> {
+        self.rows.keys().map(|(_, user)| user.as_str()).collect()
+    }
+}
+
+/// Reads a simple statistics file of a previous run written with the same column labels.
+pub fn read_baseline(filepath: &str, config: &Config) -> Result<Baseline, String> {
+    let mut reader = csv::Reader::from_path(filepath)
+        .map_err(|err| format!("failed to read {}: {}", filepath, err))?;
+    let headers = reader
+        .headers()
+        .map_err(|err| format!("failed to read the header of {}: {}", filepath, err))?
+        .clone();
+
+    let label = |name: &str| {
+        let label = config.column_labels.get(name);
+        label.map_or_else(|| name.to_string(), String::clone)
+    };
+    let position = |name: &str| headers.iter().position(|header| header == label(name));
+    let user =
+        position("User").ok_or_else(|| format!("{} has no {} column", filepath, label("User")))?;
+    let period = position("Period");
+
+    let mut rows = BTreeMap::new();
+    for record in reader.records() {
+        let record = record.map_err(|err| format!("failed to read {}: {}", filepath, err))?;
+        let key = (
+            period.map_or("", |period| &record[period]).to_string(),
+            record[user].to_string(),
+        );
+        let values = headers
+            .iter()
+            .zip(&record)
+            .filter_map(|(header, cell)| Some((header.to_string(), cell.parse().ok()?)))
+            .collect();
+        rows.insert(key, values);
+    }
+
+    Ok(Baseline {
+        columns: headers.iter().map(str::to_string).collect(),
+        has_period: period.is_some(),
+        rows,
+    })
+}
+
+/// Returns the columns of the simple statistics, with a delta column after the selected ones for
+/// every numeric column the baseline also has. Deltas of users without a baseline row are empty.
+fn simple_columns(config: &Config) -> Vec<Column> {
+    let mut columns = selected_columns(config);
+    let baseline = match &config.baseline {
+        Some(baseline) => baseline,
+        None => return columns,
+    };
+
+    let labels = header(&columns, config);
+    let mut deltas = Vec::new();
+    for (name, label) in columns.iter().map(|column| &column.name).zip(labels) {
+        if ["Period", "User", "Repo", "Branch"].contains(&name.as_str())
+            || !baseline.columns.contains(&label)
+        {
+            continue;
+        }
+
+        let column = find_column(name, config).expect("Failed to find column");
+        let baseline = Rc::clone(baseline);
+        deltas.push(Column {
+            name: format!("d{}", label),
+            metric: None,
+            ratio: column.ratio,
+            value: Box::new(move |row| {
+                let current: Option<f32> = (column.value)(row).parse().ok();
+                match (current, baseline.value(row.period, row.user, &label)) {
+                    (Some(current), Some(previous)) => (current - previous).to_string(),
+                    _ => String::new(),
+                }
+            }),
+        });
+    }
+
+    columns.extend(deltas);
+    columns
+}
+
+/// Returns the columns of the file collecting the simple statistics of every run, which start with
+/// the date of the run.
+fn merge_columns(config: &Config) -> Vec<Column> {
+    let run_date = chrono::Utc::today().format("%Y-%m-%d").to_string();
+    let mut columns = vec![Column {
+        name: "RunDate".to_string(),
+        metric: None,
+        ratio: false,
+        value: Box::new(move |_| run_date.clone()),
+    }];
+    columns.extend(selected_columns(config));
+    columns
+}
+
+/// Checks that the existing file given to `--merge-into` has the columns of the current
+/// configuration, so that rows of different runs line up.
+pub fn validate_merge_file(filepath: &str, config: &Config) -> Result<(), String> {
+    if !std::fs::metadata(filepath).is_ok_and(|metadata| metadata.len() > 0) {
+        return Ok(());
+    }
+
+    let mut reader = csv::Reader::from_path(filepath)
+        .map_err(|err| format!("failed to read {}: {}", filepath, err))?;
+    let existing: Vec<String> = reader
+        .headers()
+        .map_err(|err| format!("failed to read the header of {}: {}", filepath, err))?
+        .iter()
+        .map(str::to_string)
+        .collect();
+
+    let header = header(&merge_columns(config), config);
+    if existing != header {
+        return Err(format!(
+            "columns of {} don't match the current configuration: it has {}, expected {}",
+            filepath,
+            existing.join(","),
+            header.join(",")
+        ));
+    }
+    Ok(())
+}
+
+/// Appends the simple statistics to a CSV file collecting the rows of every run, see
+/// `validate_merge_file`. The header is only written when the file is new.
+pub fn merge_simple_stats(filepath: &str, stats: &PeriodStatistics, config: &Config) {
+    let columns = merge_columns(config);
+    let is_new = !std::fs::metadata(filepath).is_ok_and(|metadata| metadata.len() > 0);
+
+    let file = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(filepath)
+        .expect("Failed to open merge file");
+    let mut writer = csv::Writer::from_writer(Output::new(filepath, file, config));
+    if is_new {
+        writer
+            .write_record(header(&columns, config))
+            .expect("Failed to create header record");
+    }
+
+    let mut writer = Writer { writer, columns };
+    write_simple_rows(&mut writer, stats, config);
+    finish(writer);
+}
+
+fn write_simple_rows(writer: &mut Writer, stats: &PeriodStatistics, config: &Config) {
+    let users = display_names(config);
+
+    for (period, stats) in stats {
+        let avg_stats = get_average_stats(stats, "All");
+        if config.average_row == AverageRow::First {
+            write_record(writer, period, &config.average_label, "All", &avg_stats);
+        }
+
+        let mut rows = Vec::new();
+
+        for (user, repos) in stats.iter().filter(|(user, _)| config.is_reported(user)) {
+            rows.push(Row {
+                period,
+                user: &users[user],
+                repo: "All",
+                branch: "All",
+                stats: repos.get("All").expect("Failed to get 'All' row"),
+            });
+        }
+
+        sort_rows(&mut rows, config);
+        for row in &rows {
+            write_row(writer, row);
+        }
+
+        if config.average_row == AverageRow::Last {
+            write_record(writer, period, &config.average_label, "All", &avg_stats);
+        }
+    }
+}
+
+pub fn write_detailed_stats(stats: &PeriodStatistics, config: &Config) {
+    let columns = selected_columns(config);
+    let mut writer = new_csv_writer(&output_file("detailed", config), columns, config);
+    let users = display_names(config);
+    let prefix = repo_prefix(stats, config);
+
+    for (period, stats) in stats {
+        let mut rows = Vec::new();
+
+        for (user, repos) in stats.iter().filter(|(user, _)| config.is_reported(user)) {
+            for (repo, stats) in repos {
+                let repo = strip_repo(repo, &prefix);
+                rows.push(Row {
+                    period,
+                    user: &users[user],
+                    repo,
+                    branch: "All",
+                    stats,
+                });
+
+                for (branch, stats) in &stats.branches {
+                    rows.push(Row {
+                        period,
+                        user: &users[user],
+                        repo,
+                        branch,
+                        stats,
+                    });
+                }
+            }
+        }
+
+        sort_rows(&mut rows, config);
+        for row in &rows {
+            write_row(&mut writer, row);
+        }
+
+        let repos: BTreeSet<&String> = stats.values().flat_map(|repos| repos.keys()).collect();
+
+        for repo in repos {
+            let avg_stats = get_average_stats(stats, repo);
+            let repo_name = strip_repo(repo, &prefix);
+            write_record(
+                &mut writer,
+                period,
+                &config.average_label,
+                repo_name,
+                &avg_stats,
+            );
+        }
+    }
+
+    finish(writer);
+}
+
+/// Columns telling the rows of the simple and detailed statistics apart.
+const KEY_COLUMNS: [&str; 4] = ["Period", "User", "Repo", "Branch"];
+
+type RowKey = [String; 4];
+
+/// Returns the period, user, repo and branch of every row `write_simple_stats` writes.
+fn simple_keys(stats: &PeriodStatistics, config: &Config) -> Vec<RowKey> {
+    let users = display_names(config);
+    let mut keys = Vec::new();
+
+    for (period, stats) in stats {
+        let key = |user: &str| [period, user, "All", "All"].map(str::to_string);
+        if config.average_row != AverageRow::None {
+            keys.push(key(&config.average_label));
+        }
+        for user in stats.keys().filter(|user| config.is_reported(user)) {
+            keys.push(key(&users[user]));
+        }
+    }
+
+    keys
+}
+
+/// Returns the period, user, repo and branch of every row `write_detailed_stats` writes.
+fn detailed_keys(stats: &PeriodStatistics, config: &Config) -> Vec<RowKey> {
+    let users = display_names(config);
+    let prefix = repo_prefix(stats, config);
+    let mut keys = Vec::new();
+
+    for (period, stats) in stats {
+        let key = |user: &str, repo: &str, branch: &str| {
+            [period, user, strip_repo(repo, &prefix), branch].map(str::to_string)
+        };
+        for (user, repos) in stats.iter().filter(|(user, _)| config.is_reported(user)) {
+            for (repo, stats) in repos {
+                keys.push(key(&users[user], repo, "All"));
+                for branch in stats.branches.keys() {
+                    keys.push(key(&users[user], repo, branch));
+                }
+            }
+        }
+
+        let repos: BTreeSet<&String> = stats.values().flat_map(|repos| repos.keys()).collect();
+        for repo in repos {
+            keys.push(key(&config.average_label, repo, "All"));
+        }
+    }
+
+    keys
+}
+
+/// Reads back an output file and checks that it has the header of the columns and exactly the rows
+/// of the keys, in any order. Only the key columns among the selected ones are compared, in the
+/// output encoding.
+fn validate_file(
+    filepath: &str,
+    columns: &[Column],
+    mut keys: Vec<RowKey>,
+    config: &Config,
+) -> Result<(), String> {
+    let content =
+        std::fs::read(filepath).map_err(|err| format!("failed to read {}: {}", filepath, err))?;
+
+    let encode = |text: &str| {
+        let bytes = config.encoding.encode(text, config.lossy);
+        bytes.expect("Failed to encode output text")
+    };
+    let mut reader = csv::Reader::from_reader(content.as_slice());
+    let headers = reader
+        .byte_headers()
+        .map_err(|err| format!("failed to read the header of {}: {}", filepath, err))?;
+    let expected: Vec<Vec<u8>> = header(columns, config)
+        .iter()
+        .map(|label| encode(label))
+        .collect();
+    if headers.iter().ne(expected.iter().map(Vec::as_slice)) {
+        return Err(format!("{} doesn't have the expected header", filepath));
+    }
+
+    let positions: Vec<(usize, usize)> = KEY_COLUMNS
+        .iter()
+        .enumerate()
+        .filter_map(|(key, name)| {
+            let position = columns.iter().position(|column| column.name == *name)?;
+            Some((key, position))
+        })
+        .collect();
+
+    let mut rows = Vec::new();
+    for record in reader.byte_records() {
+        let record = record.map_err(|err| format!("{} is malformed: {}", filepath, err))?;
+        let row: Vec<Vec<u8>> = positions
+            .iter()
+            .map(|(_, position)| record[*position].to_vec())
+            .collect();
+        rows.push(row);
+    }
+
+    if rows.len() != keys.len() {
+        return Err(format!(
+            "{} has {} rows, expected {}",
+            filepath,
+            rows.len(),
+            keys.len()
+        ));
+    }
+
+    let mut expected: Vec<Vec<Vec<u8>>> = keys
+        .drain(..)
+        .map(|key| {
+            positions
+                .iter()
+                .map(|(index, _)| encode(&key[*index]))
+                .collect()
+        })
+        .collect();
+    expected.sort();
+    rows.sort();
+    if let Some((missing, _)) = expected
+        .iter()
+        .zip(&rows)
+        .find(|(expected, row)| expected != row)
+    {
+        let cells: Vec<_> = missing
+            .iter()
+            .map(|cell| String::from_utf8_lossy(cell))
+            .collect();
+        return Err(format!("{} has no row for {}", filepath, cells.join(", ")));
+    }
+
+    Ok(())
+}
+
+/// Reads back the simple and detailed statistics after writing them, for `--validate-output`,
+/// returning an error if a file was truncated or is malformed.
+pub fn validate_output(stats: &PeriodStatistics, config: &Config) -> Result<(), String> {
+    validate_file(
+        &output_file("stats", config),
+        &simple_columns(config),
+        simple_keys(stats, config),
+        config,
+    )?;
+    validate_file(
+        &output_file("detailed", config),
+        &selected_columns(config),
+        detailed_keys(stats, config),
+        config,
+    )
+}
+
+/// Writes the pivot metric of every reported user in a matrix with a row per repo and a column
+/// per user. Cells of users without changes, comments or approvals in a repo are left empty.
+pub fn write_pivot(stats: &PeriodStatistics, config: &Config) {
+    let metric = config.pivot.as_ref().expect("Failed to get pivot metric");
+    let column = find_column(metric, config).expect("Failed to find column");
+    let filepath = output_file("pivot", config);
+    let mut writer = csv::Writer::from_writer(Output::create(&filepath, config));
+    let names = display_names(config);
+    let prefix = repo_prefix(stats, config);
+
+    let users: Vec<&String> = names
+        .keys()
+        .filter(|user| config.is_reported(user))
+        .collect();
+    let mut header = vec!["Period", "Repo"];
+    header.extend(users.iter().map(|user| names[*user].as_str()));
+    writer
+        .write_record(&header[usize::from(config.bucket.is_none())..])
+        .expect("Failed to create header record");
+
+    for (period, stats) in stats {
+        let repos: BTreeSet<&String> = stats.values().flat_map(|repos| repos.keys()).collect();
+
+        for repo in repos {
+            let mut record = vec![period.to_string(), strip_repo(repo, &prefix).to_string()];
+            for user in &users {
+                let user_stats = stats.get(*user).and_then(|repos| repos.get(repo));
+                record.push(user_stats.map_or_else(String::new, |stats| {
+                    (column.value)(&Row {
+                        period,
+                        user: &names[*user],
+                        repo,
+                        branch: "All",
+                        stats,
+                    })
+                }));
+            }
+            writer
+                .write_record(&record[usize::from(config.bucket.is_none())..])
+                .expect("Failed to write record to csv file");
+        }
+    }
+
+    finish_csv(writer);
+}
+
+/// Writes the repos with the most activity of all users, i.e. the sum of their changes, comments
+/// made and approvals, with the totals they add up from.
+pub fn write_top_repos(stats: &PeriodStatistics, count: usize, config: &Config) {
+    let filepath = output_file("repos", config);
+    let mut writer = csv::Writer::from_writer(Output::create(&filepath, config));
+    let prefix = repo_prefix(stats, config);
+
+    let header = ["Period", "Repo", "CH", "CM", "AP", "Users"];
+    writer
+        .write_record(&header[usize::from(config.bucket.is_none())..])
+        .expect("Failed to create header record");
+
+    for (period, stats) in stats {
+        let repos: BTreeSet<&String> = stats.values().flat_map(|repos| repos.keys()).collect();
+        let mut totals: Vec<(&String, Stats)> = repos
+            .into_iter()
+            .filter(|repo| *repo != "All")
+            .map(|repo| (repo, get_total_stats(stats, repo)))
+            .collect();
+        // Sorting is stable, so repos with the same activity stay in name order.
+        totals.sort_by_key(|(_, total)| {
+            Reverse(total.changes + total.comments_made + total.approvals)
+        });
+
+        for (repo, total) in totals.iter().take(count) {
+            let record = [
+                period.to_string(),
+                strip_repo(repo, &prefix).to_string(),
+                total.changes.to_string(),
+                total.comments_made.to_string(),
+                total.approvals.to_string(),
+                total.contributors.to_string(),
+            ];
+            writer
+                .write_record(&record[usize::from(config.bucket.is_none())..])
+                .expect("Failed to write record to csv file");
+        }
+    }
+
+    finish_csv(writer);
+}
+
+/// Writes the comments and approvals that flowed along every configured reviewer and author pair.
+pub fn write_pairs(stats: &PeriodStatistics, config: &Config) {
+    let filepath = output_file("pairs", config);
+    let mut writer = csv::Writer::from_writer(Output::create(&filepath, config));
+    let names = display_names(config);
+
+    let header = ["Period", "Reviewer", "Author", "CM", "AP"];
+    writer
+        .write_record(&header[usize::from(config.bucket.is_none())..])
+        .expect("Failed to create header record");
+
+    for (period, stats) in stats {
+        for pair in &config.pairs {
+            let (comments, approvals) = stats
+                .get(&pair.reviewer)
+                .and_then(|repos| repos.get("All"))
+                .and_then(|all| all.pair_reviews.get(&pair.author))
+                .copied()
+                .unwrap_or_default();
+            let record = [
+                period.to_string(),
+                names[&pair.reviewer].clone(),
+                names[&pair.author].clone(),
+                comments.to_string(),
+                approvals.to_string(),
+            ];
+            writer
+                .write_record(&record[usize::from(config.bucket.is_none())..])
+                .expect("Failed to write record to csv file");
+        }
+    }
+
+    finish_csv(writer);
+}
+
+pub fn write_pseudonyms(filepath: &str, config: &Config) {
+    let mut writer = csv::Writer::from_writer(Output::create(filepath, config));
+    let users = config.user_names();
+
+    writer
+        .write_record(["Pseudonym", "Username", "Fullname"])
+        .expect("Failed to create header record");
+
+    for (username, pseudonym) in config.user_pseudonyms() {
+        writer
+            .write_record([&pseudonym, &username, &users[&username]])
+            .expect("Failed to write record to csv file");
+    }
+
+    finish_csv(writer);
+}
+
+/// Writes the merged changes in repos of `repo_min_approvers`, matched as glob patterns, that were
+/// approved by fewer people other than the owner than the repo requires.
+pub fn write_violations(filepath: &str, reviews: &[Review], config: &Config) {
+    let mut writer = csv::Writer::from_writer(Output::create(filepath, config));
+    let users = display_names(config);
+    let dates = config.user_dates();
+
+    writer
+        .write_record(["Change", "Repo", "Owner", "Approvers", "Required"])
+        .expect("Failed to create header record");
+
+    for review in reviews {
+        let required = match glob::lookup(&config.repo_min_approvers, &review.project) {
+            Some(required) => *required,
+            None => continue,
+        };
+        let (from, to) = &dates[&review.owner.username];
+        if !config.is_counted_review(review)
+            || !review.is_within_date(from, to, &config.submit_label)
+            || !review.is_merged(&config.submit_label)
+        {
+            continue;
+        }
+
+        let approvers = review
+            .approvers(
+                &config.approval_labels,
+                &config.repo_approval_min,
+                config.approval_patch_sets,
+            )
+            .len() as u32;
+        if approvers < required {
+            writer
+                .write_record([
+                    &review.number.to_string(),
+                    &review.project,
+                    &users[&review.owner.username],
+                    &approvers.to_string(),
+                    &required.to_string(),
+                ])
+                .expect("Failed to write record to csv file");
+        }
+    }
+
+    finish_csv(writer);
+}
+
+/// Writes a row for every counted change with the numbers that go into its owner's statistics, for
+/// analysis beyond the aggregates. The submit date is empty for changes that aren't merged.
+pub fn write_changes(filepath: &str, reviews: &[Review], config: &Config) {
+    let mut writer = csv::Writer::from_writer(Output::create(filepath, config));
+    let users = display_names(config);
+    let dates = config.user_dates();
+
+    let header = [
+        "Period",
+        "Change",
+        "Owner",
+        "Repo",
+        "Branch",
+        "Submitted",
+        "PS",
+        "CR",
+        "DR",
+        "Lines",
+    ];
+    writer
+        .write_record(&header[usize::from(config.bucket.is_none())..])
+        .expect("Failed to create header record");
+
+    for review in reviews {
+        let (from, to) = &dates[&review.owner.username];
+        if !config.is_counted_review(review)
+            || !review.is_within_date(from, to, &config.submit_label)
+        {
+            continue;
+        }
+
+        let period = match (config.bucket, review.counted_time(&config.submit_label)) {
+            (Some(bucket), Some(time)) => bucket.period(time),
+            _ => "All".to_string(),
+        };
+        let submitted = review
+            .submit_time(&config.submit_label)
+            .map(|time| NaiveDateTime::from_timestamp(time, 0).date().to_string());
+        let reviewers = review.reviewers(&config.ci_accounts, &config.submit_label);
+        let record = [
+            period,
+            review.number.to_string(),
+            users[&review.owner.username].clone(),
+            review.project.clone(),
+            review.branch.clone(),
+            submitted.unwrap_or_default(),
+            review.patch_set_count().to_string(),
+            review.comments_received(&config.ci_accounts).to_string(),
+            reviewers.len().to_string(),
+            review
+                .lines_changed()
+                .map(|lines| lines.to_string())
+                .unwrap_or_default(),
+        ];
+        writer
+            .write_record(&record[usize::from(config.bucket.is_none())..])
+            .expect("Failed to write record to csv file");
+    }
+
+    finish_csv(writer);
+}
+
+/// Writes the number of approvals every user gave in each delay bucket after the patch set upload.
+pub fn write_approval_delays(filepath: &str, stats: &PeriodStatistics, config: &Config) {
+    let mut writer = csv::Writer::from_writer(Output::create(filepath, config));
+    let users = display_names(config);
+
+    let mut header = vec!["Period", "User"];
+    header.extend(DELAY_BUCKETS);
+    writer
+        .write_record(&header[usize::from(config.bucket.is_none())..])
+        .expect("Failed to create header record");
+
+    for (period, stats) in stats {
+        for (user, repos) in stats.iter().filter(|(user, _)| config.is_reported(user)) {
+            let delays = repos["All"].approval_delays.iter().map(u32::to_string);
+
+            let mut record = vec![period.to_string(), users[user].to_string()];
+            record.extend(delays);
+            writer
+                .write_record(&record[usize::from(config.bucket.is_none())..])
+                .expect("Failed to write record to csv file");
+        }
+    }
+
+    finish_csv(writer);
+}
+
+/// Writes how many changes every user last voted each Code-Review value on.
+pub fn write_vote_histogram(filepath: &str, stats: &PeriodStatistics, config: &Config) {
+    let mut writer = csv::Writer::from_writer(Output::create(filepath, config));
+    let users = display_names(config);
+
+    let mut header = vec!["Period".to_string(), "User".to_string()];
+    header.extend(CODE_REVIEW_VOTES.iter().map(|vote| format!("{:+}", vote)));
+    writer
+        .write_record(&header[usize::from(config.bucket.is_none())..])
+        .expect("Failed to create header record");
+
+    for (period, stats) in stats {
+        for (user, repos) in stats.iter().filter(|(user, _)| config.is_reported(user)) {
+            let votes = repos["All"].code_review_votes.iter().map(u32::to_string);
+
+            let mut record = vec![period.to_string(), users[user].to_string()];
+            record.extend(votes);
+            writer
+                .write_record(&record[usize::from(config.bucket.is_none())..])
+                .expect("Failed to write record to csv file");
+        }
+    }
+
+    finish_csv(writer);
+}
+
+/// Writes the users included in the run with the dates their changes were counted in.
+pub fn write_users(filepath: &str, config: &Config) {
+    let mut writer = csv::Writer::from_writer(Output::create(filepath, config));
+    let users = config.user_names();
+    let dates = config.user_dates();
+
+    writer
+        .write_record(["Username", "Fullname", "From", "To"])
+        .expect("Failed to create header record");
+
+    for username in users.keys().filter(|user| config.is_reported(user)) {
+        let (from, to) = &dates[username];
+        writer
+            .write_record([
+                username,
+                &users[username],
+                &from.to_string(),
+                &to.to_string(),
+            ])
+            .expect("Failed to write record to csv file");
+    }
+
+    finish_csv(writer);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(extra: &str) -> Config {
+        let config = format!(
+            "server = \"gerrit\"\nport = \"29418\"\nfrom = 2019-01-01\nto = 2019-12-31\nuser = []\n{}",
+            extra
+        );
+        toml::from_str(&config).expect("Failed to parse config")
+    }
+
+    fn assert_finite(stats: &Stats, config: &Config) {
+        let columns = all_columns(config);
+        let record = format_record(&columns, "All", "Jane Doe", "All", stats);
+
+        assert_eq!(record.len(), columns.len());
+        for (column, value) in columns.iter().zip(&record) {
+            assert!(
+                !["NaN", "inf", "-inf"].contains(&value.as_str()),
+                "{} is {}",
+                column.name,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn record_without_changes_has_no_nan() {
+        assert_finite(&Stats::new(), &config(""));
+    }
+
+    #[test]
+    fn record_with_zero_denominators_has_no_nan() {
+        let stats = Stats {
+            changes: 3,
+            comments_received: 7,
+            self_merges: 1,
+            approvals: 2,
+            ..Stats::new()
+        };
+        assert_finite(&stats, &config(""));
+    }
+
+    #[test]
+    fn normalized_record_without_active_days_has_no_nan() {
+        let mut config = config("score_weights = { \"CR/CH\" = 1.0, CH = 2.0 }");
+        config.normalize_by = Some(Normalize::ActiveDays);
+        config.score = true;
+        let stats = Stats {
+            changes: 3,
+            ..Stats::new()
+        };
+        assert_finite(&stats, &config);
+    }
+
+    #[test]
+    fn ratio_cell_without_denominator_is_empty() {
+        let config = config("");
+        let columns = all_columns(&config);
+        let record = format_record(&columns, "All", "Jane Doe", "All", &Stats::new());
+
+        let index = columns
+            .iter()
+            .position(|column| column.name == "CR/CH")
+            .expect("Failed to find column");
+        assert_eq!(record[index], "");
+    }
+
+    #[test]
+    fn text_cells_that_parse_as_numbers_are_kept() {
+        let config = config("");
+        let columns = all_columns(&config);
+        let record = format_record(&columns, "All", "Nan", "Infinity", &Stats::new());
+
+        assert_eq!(record[1..3], ["Nan", "Infinity"]);
+    }
+
+    #[test]
+    fn label_is_added_to_file_names() {
+        let mut config = config("");
+        config.label = Some(sanitize_label("2024 Q1/final").expect("Failed to sanitize label"));
+        config.output_prefix = "reports/".to_string();
+
+        assert_eq!(
+            output_file("stats", &config),
+            "reports/stats-2024_Q1_final.csv"
+        );
+        assert_eq!(sanitize_label("  "), Err("the label is empty".to_string()));
+    }
+
+    #[test]
+    fn validate_truncated_file() {
+        let config = config("");
+        let columns: Vec<Column> = ["User", "CH"]
+            .iter()
+            .map(|name| find_column(name, &config).expect("Failed to find column"))
+            .collect();
+        let keys = || {
+            ["Jane Doe", "John Doe"]
+                .map(|user| ["All", user, "All", "All"].map(str::to_string))
+                .to_vec()
+        };
+        let filepath = std::env::temp_dir()
+            .join(format!("gerrit-stats-validate-{}.csv", std::process::id()))
+            .display()
+            .to_string();
+
+        std::fs::write(&filepath, "User,CH\nJohn Doe,3\nJane Doe,5\n").expect("Failed to write");
+        assert_eq!(validate_file(&filepath, &columns, keys(), &config), Ok(()));
+
+        std::fs::write(&filepath, "User,CH\nJohn Doe,3\nJane").expect("Failed to write");
+        let err = validate_file(&filepath, &columns, keys(), &config).unwrap_err();
+        assert!(err.contains("is malformed"), "{}", err);
+
+        std::fs::write(&filepath, "User,CH\nJohn Doe,3\n").expect("Failed to write");
+        let err = validate_file(&filepath, &columns, keys(), &config).unwrap_err();
+        assert!(err.ends_with("has 1 rows, expected 2"), "{}", err);
+
+        std::fs::remove_file(&filepath).expect("Failed to remove file");
+    }
+
+    #[test]
+    fn registered_metrics_have_columns() {
+        let mut registered = config(
+            "[[metric]]\nname = \"long_reviews\"\nvalue = \"patch-sets\"\nmin = 3\n\n\
+             [[metric]]\nname = \"message_words\"\nvalue = \"commit-words\"\n",
+        );
+        registered.custom_metrics = registered
+            .register_metrics()
+            .expect("Failed to register metrics");
+
+        let review: Review = serde_json::from_str(
+            r#"{"project":"repo","branch":"master","id":"I1","number":1,"owner":{"name":"Jane Doe","username":"jado"},"commitMessage":"Fix","comments":[],"patchSets":[{},{},{}]}"#,
+        )
+        .expect("Failed to parse review");
+        let values: Vec<u32> = registered
+            .custom_metrics
+            .iter()
+            .map(|metric| (metric.value)(&review))
+            .collect();
+        assert_eq!(values, vec![1, 1]);
+
+        let mut stats = Stats::new();
+        stats.custom.insert("long_reviews".to_string(), 4);
+        let columns = all_columns(&registered);
+        let record = format_record(&columns, "All", "Jane Doe", "All", &stats);
+        let index = columns
+            .iter()
+            .position(|column| column.name == "long_reviews")
+            .expect("Failed to find column");
+        assert_eq!(record[index], "4");
+        assert!(metric_names(&registered).contains(&"message_words".to_string()));
+
+        let built_in = config("[[metric]]\nname = \"comments_made\"\nvalue = \"lines\"\n");
+        assert!(built_in.register_metrics().is_err());
+
+        let duplicate = config(
+            "[[metric]]\nname = \"big\"\nvalue = \"lines\"\n\n[[metric]]\nname = \"big\"\nvalue = \"lines\"\n",
+        );
+        assert!(duplicate.register_metrics().is_err());
+    }
+
+    fn repo_stats(repos: &[&str]) -> PeriodStatistics {
+        let mut user = BTreeMap::new();
+        for repo in repos {
+            user.insert(repo.to_string(), Stats::new());
+        }
+        let mut period = BTreeMap::new();
+        period.insert("jado".to_string(), user);
+        let mut stats = BTreeMap::new();
+        stats.insert("All".to_string(), period);
+        stats
+    }
+
+    #[test]
+    fn auto_repo_prefix_is_common_path_segments() {
+        let config = config("project_prefix_strip = \"auto\"");
+
+        let stats = repo_stats(&["All", "platform/core/api", "platform/core/web"]);
+        assert_eq!(repo_prefix(&stats, &config), "platform/core/");
+
+        let stats = repo_stats(&["platform/core", "platform/corelib"]);
+        assert_eq!(repo_prefix(&stats, &config), "platform/");
+
+        let stats = repo_stats(&["platform/core", "platform/core/web"]);
+        assert_eq!(repo_prefix(&stats, &config), "platform/");
+
+        let stats = repo_stats(&["platform/core"]);
+        assert_eq!(repo_prefix(&stats, &config), "platform/");
+
+        let stats = repo_stats(&["core", "web"]);
+        assert_eq!(repo_prefix(&stats, &config), "");
+
+        assert_eq!(repo_prefix(&repo_stats(&["All"]), &config), "");
+    }
+
+    #[test]
+    fn fixed_repo_prefix_is_kept() {
+        let config = config("project_prefix_strip = \"platform/\"");
+        let stats = repo_stats(&["tools/ci"]);
+        assert_eq!(repo_prefix(&stats, &config), "platform/");
+        assert_eq!(strip_repo("platform/core", "platform/"), "core");
+        assert_eq!(strip_repo("platform/", "platform/"), "platform/");
+    }
+}
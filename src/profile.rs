@@ -0,0 +1,43 @@
+use std::time::{Duration, Instant};
+
+use crate::date;
+
+/// Wall time of the phases of a run, printed with `--profile`.
+#[derive(Debug, Default)]
+pub struct Profile {
+    phases: Vec<(String, Duration)>,
+}
+
+impl Profile {
+    pub fn add(&mut self, phase: &str, time: Duration) {
+        self.phases.push((phase.to_string(), time));
+    }
+
+    /// Runs the function and records how long it took as the given phase.
+    pub fn time<T, F: FnOnce() -> T>(&mut self, phase: &str, f: F) -> T {
+        let started = Instant::now();
+        let value = f();
+        self.add(phase, started.elapsed());
+        value
+    }
+
+    /// Prints the phases to stderr, in seconds with `raw` or in readable units like `1m 30s`.
+    pub fn print(&self, raw: bool) {
+        let width = self
+            .phases
+            .iter()
+            .map(|(phase, _)| phase.len())
+            .max()
+            .unwrap_or_default();
+
+        eprintln!("Profile:");
+        for (phase, time) in &self.phases {
+            let time = if raw {
+                format!("{:.3}s", time.as_secs_f64())
+            } else {
+                date::format_duration(time.as_secs_f64())
+            };
+            eprintln!("  {:<width$}  {:>10}", phase, time, width = width);
+        }
+    }
+}
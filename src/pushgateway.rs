@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::{output, Config, PeriodStatistics};
+
+/// How long connecting, sending the metrics and waiting for the response may take each.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Builds a Prometheus text format payload with one gauge per metric, labeled by user and repo.
+fn payload(stats: &PeriodStatistics, config: &Config) -> String {
+    let pseudonyms = config.user_pseudonyms();
+    let mut gauges: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    for (period, stats) in stats {
+        for (user, repos) in stats.iter().filter(|(user, _)| config.is_reported(user)) {
+            let user = if config.anonymize {
+                &pseudonyms[user]
+            } else {
+                user
+            };
+
+            for (repo, stats) in repos {
+                let mut labels = format!(
+                    "user=\"{}\",repo=\"{}\"",
+                    escape_label(user),
+                    escape_label(repo)
+                );
+                if config.bucket.is_some() {
+                    labels.push_str(&format!(",period=\"{}\"", escape_label(period)));
+                }
+
+                for (metric, value) in output::metrics(stats, config) {
+                    let sample = format!("gerrit_stats_{}{{{}}} {}", metric, labels, value);
+                    gauges.entry(metric).or_default().push(sample);
+                }
+            }
+        }
+    }
+
+    let mut payload = String::new();
+    for (metric, samples) in gauges {
+        payload.push_str(&format!("# TYPE gerrit_stats_{} gauge\n", metric));
+        for sample in samples {
+            payload.push_str(&sample);
+            payload.push('\n');
+        }
+    }
+    payload
+}
+
+/// Connects to the first address the host resolves to that accepts within the timeout.
+fn connect(host_port: &str) -> std::io::Result<TcpStream> {
+    let mut last_err = None;
+    for address in host_port.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = Some(err),
+        }
+    }
+    Err(last_err
+        .unwrap_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no addresses found")))
+}
+
+/// Pushes collected stats to a Prometheus pushgateway at `http://host[:port][/path]`.
+pub fn push(url: &str, stats: &PeriodStatistics, config: &Config) -> Result<(), String> {
+    let address = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("unsupported pushgateway url '{}', expected http://", url))?;
+    let (host, path) = match address.find('/') {
+        Some(index) => (&address[..index], address[index..].trim_end_matches('/')),
+        None => (address, ""),
+    };
+    let host_port = if host.contains(':') {
+        host.to_string()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let body = payload(stats, config);
+    let request = format!(
+        "POST {}/metrics/job/gerrit_stats HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    );
+
+    let mut stream = connect(&host_port)
+        .map_err(|err| format!("failed to connect to {}: {}", host_port, err))?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(TIMEOUT)))
+        .map_err(|err| format!("failed to set timeouts: {}", err))?;
+    stream
+        .write_all(request.as_bytes())
+        .map_err(|err| format!("failed to send metrics: {}", err))?;
+
+    let mut status = String::new();
+    BufReader::new(stream)
+        .read_line(&mut status)
+        .map_err(|err| format!("failed to read response: {}", err))?;
+
+    let status = status.trim_end();
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(format!("pushgateway responded with '{}'", status)),
+    }
+}
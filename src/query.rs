@@ -0,0 +1,252 @@
+use futures::future::join_all;
+use futures::{Future, Stream};
+use std::cell::RefCell;
+use std::io;
+use std::process::Command;
+use std::rc::Rc;
+use std::time::Instant;
+use tokio_core::reactor::{Core, Timeout};
+use tokio_process::CommandExt;
+use tokio_signal::unix::{Signal, SIGINT, SIGTERM};
+
+use crate::profile::Profile;
+use crate::review::{self, Review};
+use crate::schema;
+use crate::{AttributeBy, Config, User};
+
+/// Returns a future that resolves on the first SIGTERM or SIGINT.
+fn interrupted(core: &Core) -> impl Future<Item = (), Error = io::Error> {
+    let handle = core.handle().new_tokio_handle().clone();
+    let term = Signal::with_handle(SIGTERM, &handle).flatten_stream();
+    let int = Signal::with_handle(SIGINT, &handle).flatten_stream();
+
+    term.select(int)
+        .into_future()
+        .map(|_| ())
+        .map_err(|(err, _)| err)
+}
+
+/// Queries Gerrit over ssh for merged changes of every configured user. Returns the reviews with
+/// the number of queries that failed, and whether the queries were interrupted by a signal. On
+/// SIGTERM or SIGINT no more queries are spawned, running ones are killed and the reviews of the
+/// finished ones are returned. Fetch and parse times are recorded in the profile.
+pub fn query_reviews(
+    config: &Config,
+    ssh_user: &str,
+    profile: &mut Profile,
+) -> (Vec<Review>, usize, bool) {
+    let cmd_args = [
+        "-p",
+        config.port.as_str(),
+        &format!("{}@{}", ssh_user, config.server),
+        "gerrit",
+        "query",
+    ];
+
+    let cmd_opts = [
+        "--all-approvals",
+        "--all-reviewers",
+        "--comments",
+        "--commit-message",
+        "--dependencies",
+        "--files",
+        "--format",
+        "JSON",
+    ];
+    // Approvals are still fetched for the submit approval that changes are dated by.
+    let changes_only_opts = [
+        "--all-approvals",
+        "--commit-message",
+        "--dependencies",
+        "--format",
+        "JSON",
+    ];
+    let cmd_opts: &[&str] = if config.changes_only {
+        &changes_only_opts
+    } else {
+        &cmd_opts
+    };
+
+    let mut cmds = Vec::new();
+    // Outputs of the finished queries with their time, in the order of the users.
+    let results = Rc::new(RefCell::new(Vec::new()));
+    results.borrow_mut().resize_with(config.user.len(), || None);
+    let started = Instant::now();
+    let mut core = Core::new().expect("Failed to create reactor");
+
+    if !config.quiet {
+        eprintln!("Spawning {} async tasks.", config.user.len());
+    }
+
+    for (index, user) in config.user.iter().enumerate() {
+        let mut command = Command::new("ssh");
+        command
+            .stdout(std::process::Stdio::piped())
+            .args(cmd_args)
+            .args(cmd_opts)
+            .args(status_query(&config.status))
+            .args(project_query(&config.projects))
+            .arg(format!(
+                "after:{}",
+                user.from.as_ref().unwrap().utc_date("00:00:00")
+            ))
+            .arg(format!(
+                "before:{}",
+                user.to.as_ref().unwrap().utc_date("23:59:59")
+            ))
+            .arg(owner_query(user, config.attribute_by))
+            .args(config.query_extra.split_whitespace());
+
+        // Queries are spaced out by the spawn delay, their time is measured from the spawn.
+        let delay = config.spawn_delay * index as u32;
+        let results = Rc::clone(&results);
+        let child = Timeout::new(delay, &core.handle())
+            .expect("Failed to create spawn timer")
+            .and_then(move |_| {
+                let spawned = Instant::now();
+                command
+                    .spawn_async()
+                    .expect("Failed to spawn command")
+                    .wait_with_output()
+                    .map(move |output| {
+                        results.borrow_mut()[index] = Some((output, spawned.elapsed()));
+                    })
+            });
+
+        cmds.push(child);
+    }
+
+    if !config.quiet {
+        eprintln!("Starting work. This might take a while.");
+    }
+
+    // The signal is polled first so it wins over queries killed by the same Ctrl-C. Dropping the
+    // unfinished queries kills their ssh processes.
+    let work = interrupted(&core)
+        .map(|_| true)
+        .select(join_all(cmds).map(|_| false));
+    let interrupted = core
+        .run(work)
+        .map(|(interrupted, _)| interrupted)
+        .map_err(|(err, _)| err)
+        .expect("Failed to run work");
+    profile.add("fetch", started.elapsed());
+
+    let ret = results.replace(Vec::new());
+    if interrupted {
+        eprintln!(
+            "Interrupted, writing partial results of {} of {} queries.",
+            ret.iter().flatten().count(),
+            ret.len()
+        );
+    }
+
+    let slowest = config
+        .user
+        .iter()
+        .zip(&ret)
+        .filter_map(|(user, result)| result.as_ref().map(|(_, time)| (user, time)))
+        .max_by_key(|(_, time)| *time);
+    if let Some((user, time)) = slowest {
+        profile.add(&format!("slowest query ({})", user.username), *time);
+    }
+
+    let started = Instant::now();
+
+    let mut outputs = Vec::new();
+    let mut failed = 0;
+
+    for (user, (output, _)) in config
+        .user
+        .iter()
+        .zip(&ret)
+        .filter_map(|(user, result)| result.as_ref().map(|result| (user, result)))
+    {
+        if !output.status.success() {
+            eprintln!(
+                "Query for user '{}' failed: {}",
+                user.username, output.status
+            );
+            failed += 1;
+        }
+        let output = std::str::from_utf8(&output.stdout).expect("Failed to read command output");
+        if config.strict_parse {
+            schema::check_fields(
+                output,
+                &format!("output of query for user '{}'", user.username),
+            );
+        }
+        outputs.push(output);
+    }
+    let reviews = review::parse_outputs(&outputs, config.parse_threads);
+
+    profile.add("parse", started.elapsed());
+
+    (reviews, failed, interrupted)
+}
+
+/// Returns the status part of the query, which is left out to query changes of every status.
+fn status_query(status: &str) -> Option<String> {
+    match status {
+        "all" => None,
+        status => Some(format!("status:{}", status)),
+    }
+}
+
+/// Checks that the project names can be put in a query, Gerrit's ssh command line splits them at
+/// whitespace and braces are used for quoting.
+pub fn validate_projects(projects: &[String]) -> Result<(), String> {
+    for project in projects {
+        if project.is_empty() || project.contains(|c: char| c.is_whitespace() || "{}".contains(c)) {
+            return Err(format!("invalid project name '{}'", project));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the project part of the query matching any of the projects, which is left out without
+/// projects to query changes of every project. Names with characters Gerrit's query syntax gives a
+/// meaning to, e.g. parentheses, are quoted in braces.
+fn project_query(projects: &[String]) -> Option<String> {
+    let predicates: Vec<String> = projects
+        .iter()
+        .map(|project| {
+            let plain = project
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "/_.-".contains(c));
+            if plain {
+                format!("project:{}", project)
+            } else {
+                format!("project:{{{}}}", project)
+            }
+        })
+        .collect();
+    match predicates.as_slice() {
+        [] => None,
+        [predicate] => Some(predicate.clone()),
+        predicates => Some(format!("({})", predicates.join(" OR "))),
+    }
+}
+
+/// Returns the owner part of the query, matching the username and all of its aliases as the owner,
+/// the uploader of the last patch set, or either of them.
+fn owner_query(user: &User, attribute_by: AttributeBy) -> String {
+    let predicates: &[&str] = match attribute_by {
+        AttributeBy::Owner => &["owner"],
+        AttributeBy::Uploader => &["uploader"],
+        AttributeBy::Both => &["owner", "uploader"],
+    };
+
+    let owners: Vec<String> = std::iter::once(&user.username)
+        .chain(&user.aliases)
+        .flat_map(|username| {
+            predicates
+                .iter()
+                .map(move |predicate| format!("{}:{}", predicate, username))
+        })
+        .collect();
+    match owners.as_slice() {
+        [owner] => owner.clone(),
+        owners => format!("({})", owners.join(" OR ")),
+    }
+}
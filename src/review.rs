@@ -1,37 +1,100 @@
-use chrono::NaiveDateTime;
+use crate::date::{self, Date};
+use crate::glob;
+use crate::{ApprovalLabel, ApprovalPatchSets, SelfCommentCount};
 use serde::Deserialize;
-use std::collections::HashMap;
-use toml::value::Datetime;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+/// Code-Review votes counted per reviewer for `--vote-histogram`, in the order of their columns.
+pub const CODE_REVIEW_VOTES: [i32; 4] = [-2, -1, 1, 2];
 
 #[derive(Debug, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct Review {
     pub project: String,
     pub branch: String,
-    pub id: String,
-    number: i32,
+    pub number: i32,
     pub owner: User,
     commit_message: String,
     pub comments: Vec<Comment>,
     pub patch_sets: Vec<PatchSet>,
+    created_on: Option<i64>,
+    last_updated: Option<i64>,
+    #[serde(default)]
+    pub depends_on: Vec<Dependency>,
+    /// Number of fetched changes in the stack up to and including this one, see `set_stack_depths`.
+    #[serde(skip)]
+    pub stack_depth: u32,
+    /// Whether a fetched change reverts this one, see `set_reverted`.
+    #[serde(skip)]
+    pub reverted: bool,
 }
 
 #[derive(Debug, Deserialize, Default)]
+pub struct Dependency {
+    pub number: i32,
+}
+
+#[derive(Debug, Clone, Deserialize, Default)]
 pub struct User {
-    pub name: String,
+    /// Empty for accounts without a username, e.g. external or deleted accounts, see `resolve_users`.
+    #[serde(default)]
     pub username: String,
+    pub email: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Default)]
 pub struct Comment {
     pub reviewer: User,
     pub message: String,
+    pub timestamp: Option<i64>,
+    /// Thread linkage of inline comments, only present in some Gerrit versions.
+    pub id: Option<String>,
+    #[serde(rename = "inReplyTo")]
+    pub in_reply_to: Option<String>,
+    /// Whether the comment was left unresolved, only present in some Gerrit versions.
+    pub unresolved: Option<bool>,
+    /// File and line of inline comments, e.g. `src/main.rs`, or `/COMMIT_MSG` for comments on the
+    /// commit message.
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    /// Where the comment starts when it spans several lines, only present in some Gerrit versions.
+    pub range: Option<CommentRange>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct CommentRange {
+    #[serde(rename = "startLine")]
+    pub start_line: u32,
+}
+
+/// File changed by a patch set, as listed with `--files`.
+#[derive(Debug, Deserialize, Default)]
+pub struct PatchFile {
+    pub file: String,
+    /// Kind of the change to the file, e.g. `ADDED` or `MODIFIED`.
+    #[serde(rename = "type")]
+    pub change_type: Option<String>,
+    #[serde(default)]
+    pub insertions: i64,
 }
 
 #[derive(Debug, Deserialize, Default)]
 pub struct PatchSet {
+    #[serde(rename = "createdOn")]
+    pub created_on: Option<i64>,
+    pub uploader: Option<User>,
+    /// Commit sha of the patch set.
+    pub revision: Option<String>,
+    /// Kind of the change to the previous patch set, e.g. `REWORK` or `TRIVIAL_REBASE`.
+    pub kind: Option<String>,
     pub approvals: Option<Vec<Approval>>,
     pub comments: Option<Vec<Comment>>,
+    pub files: Option<Vec<PatchFile>>,
+    #[serde(rename = "sizeInsertions")]
+    pub size_insertions: Option<i64>,
+    /// Gerrit reports deletions as a negative number.
+    #[serde(rename = "sizeDeletions")]
+    pub size_deletions: Option<i64>,
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -44,55 +107,62 @@ pub struct Approval {
     pub by: User,
 }
 
-trait Timestamp {
-    fn timestamp(&self, time: &str) -> i64;
-}
+impl Review {
+    fn submit_approval(&self, submit_label: &str) -> Option<&Approval> {
+        let patch = self.patch_sets.last()?;
 
-/// Extends toml::value::Datetime with a function that returns timestamp.
-impl Timestamp for Datetime {
-    fn timestamp(&self, time: &str) -> i64 {
-        let datetime = format!("{}T{}", &self.to_string(), time);
-        NaiveDateTime::parse_from_str(&datetime, "%Y-%m-%dT%H:%M:%S")
-            .expect("Failed to parse datetime")
-            .timestamp()
+        patch
+            .approvals
+            .as_ref()?
+            .iter()
+            .find(|approval| approval.review_type == submit_label)
     }
-}
 
-impl Review {
-    pub fn new(line: &str) -> Self {
-        serde_json::from_str(line).expect("Failed to parse json")
+    /// Returns the time the change was submitted, as recorded by the submit approval.
+    pub fn submit_time(&self, submit_label: &str) -> Option<i64> {
+        self.submit_approval(submit_label)
+            .map(|approval| approval.granted_on)
     }
 
-    pub fn is_within_date(&self, from: &Datetime, to: &Datetime) -> bool {
-        let from = from.timestamp("00:00:00");
-        let to = to.timestamp("23:59:59");
-        let patch = self
-            .patch_sets
-            .last()
-            .expect("Failed to get last patch set");
+    /// Returns whether the owner submitted the change themselves. Changes submitted by anyone
+    /// else, including service accounts, are not self merges.
+    pub fn is_self_merged(&self, submit_label: &str) -> bool {
+        self.submit_approval(submit_label)
+            .is_some_and(|approval| approval.by.username == self.owner.username)
+    }
 
-        for approval in patch
-            .approvals
-            .as_ref()
-            .expect("Failed to get approval change")
-        {
-            if approval.review_type == "SUBM"
-                && from <= approval.granted_on
-                && approval.granted_on <= to
-            {
-                return true;
-            }
-        }
+    /// Returns whether the change was merged, i.e. it has a submit approval.
+    pub fn is_merged(&self, submit_label: &str) -> bool {
+        self.submit_approval(submit_label).is_some()
+    }
+
+    /// Returns the time the change is counted at, which is when it was submitted, or when it was
+    /// last updated for changes that aren't merged.
+    pub fn counted_time(&self, submit_label: &str) -> Option<i64> {
+        self.submit_time(submit_label).or(self.last_updated)
+    }
 
-        false
+    /// Returns when the change was open for review, from its creation, or its first upload when
+    /// that isn't known, until the time it's counted at. Changes missing either end have none.
+    pub fn open_interval(&self, submit_label: &str) -> Option<(i64, i64)> {
+        let start = self.created_on.or_else(|| self.upload_times().min())?;
+        let end = self.counted_time(submit_label)?;
+        Some((start, end.max(start)))
+    }
+
+    pub fn is_within_date(&self, from: &Date, to: &Date, submit_label: &str) -> bool {
+        match self.counted_time(submit_label) {
+            Some(submitted) => date::is_within(from, to, submitted),
+            None => false,
+        }
     }
 
     pub fn repository_name(&self) -> String {
         self.project.to_string()
     }
 
-    pub fn comments_made(&self, users: &HashMap<String, String>) -> HashMap<String, u32> {
-        let mut user_comments: HashMap<String, u32> = HashMap::new();
+    pub fn comments_made(&self, users: &BTreeMap<String, String>) -> BTreeMap<String, u32> {
+        let mut user_comments: BTreeMap<String, u32> = BTreeMap::new();
 
         for patch in &self.patch_sets {
             if let Some(comments) = &patch.comments {
@@ -111,46 +181,1060 @@ impl Review {
         user_comments
     }
 
-    pub fn comments_received(&self) -> u32 {
+    /// Returns the number of comments on the change, skipping the ones made by excluded accounts.
+    pub fn comments_received(&self, excluded: &[String]) -> u32 {
         let mut received = 0u32;
 
         for patch in &self.patch_sets {
             if let Some(comments) = &patch.comments {
-                received += comments.len() as u32;
+                received += comments
+                    .iter()
+                    .filter(|comment| !excluded.contains(&comment.reviewer.username))
+                    .count() as u32;
             }
         }
 
         received
     }
 
-    pub fn approvals(&self, users: &HashMap<String, String>) -> Vec<String> {
-        let mut approval_users = Vec::new();
-        let patch = self
-            .patch_sets
-            .last()
-            .expect("Failed to get last patch set");
+    /// Returns the number of comments like `comments_received`, without the owner's own replies.
+    pub fn comments_received_from_others(&self, excluded: &[String]) -> u32 {
+        let mut excluded = excluded.to_vec();
+        excluded.push(self.owner.username.clone());
+        self.comments_received(&excluded)
+    }
 
-        for approval in patch
-            .approvals
-            .as_ref()
-            .expect("Failed to get approval change")
-        {
-            if approval.review_type == "Code-Review"
-                && approval.value == "2"
-                && users.contains_key(&approval.by.username)
+    /// Returns the number of resolved, unresolved and comments without a resolution status among the
+    /// ones counted by `comments_received`.
+    pub fn comments_received_by_resolution(&self, excluded: &[String]) -> (u32, u32, u32) {
+        let (mut resolved, mut unresolved, mut unknown) = (0u32, 0u32, 0u32);
+
+        for patch in &self.patch_sets {
+            for comment in patch
+                .comments
+                .iter()
+                .flatten()
+                .filter(|comment| !excluded.contains(&comment.reviewer.username))
             {
-                approval_users.push(approval.by.username.clone());
+                match comment.unresolved {
+                    Some(false) => resolved += 1,
+                    Some(true) => unresolved += 1,
+                    None => unknown += 1,
+                }
+            }
+        }
+
+        (resolved, unresolved, unknown)
+    }
+
+    /// Returns the number of inline comments others left on lines the change added and the number
+    /// of inline comments they left at all. Without diff hunks the split is rough: comments on a file
+    /// the patch set added or inserted lines into count as on the change, comments on files it only
+    /// deleted lines from, or on the commit message, as on context. Comments without file and line,
+    /// and comments on patch sets fetched without their files, are left out of both.
+    pub fn comments_received_on_change(&self, excluded: &[String]) -> (u32, u32) {
+        let (mut on_change, mut inline) = (0u32, 0u32);
+
+        for patch in &self.patch_sets {
+            let files = match &patch.files {
+                Some(files) => files,
+                None => continue,
+            };
+            for comment in patch
+                .comments
+                .iter()
+                .flatten()
+                .filter(|comment| !excluded.contains(&comment.reviewer.username))
+                .filter(|comment| comment.reviewer.username != self.owner.username)
+            {
+                let file = match (
+                    &comment.file,
+                    comment
+                        .line
+                        .or(comment.range.as_ref().map(|range| range.start_line)),
+                ) {
+                    (Some(file), Some(_)) => file,
+                    _ => continue,
+                };
+                inline += 1;
+                let changed = files.iter().any(|changed| {
+                    &changed.file == file
+                        && !changed.file.starts_with('/')
+                        && (changed.insertions > 0
+                            || matches!(changed.change_type.as_deref(), Some("ADDED" | "REWRITE")))
+                });
+                on_change += u32::from(changed);
+            }
+        }
+
+        (on_change, inline)
+    }
+
+    /// Returns the number of comments on the change like `comments_received`, with comments on the
+    /// last patch set counting full and comments on every earlier patch set weighted by `decay`
+    /// once more.
+    pub fn weighted_comments_received(&self, excluded: &[String], decay: f64) -> f64 {
+        let last = self.patch_sets.len().saturating_sub(1);
+        let mut received = 0.0;
+
+        for (index, patch) in self.patch_sets.iter().enumerate() {
+            if let Some(comments) = &patch.comments {
+                let count = comments
+                    .iter()
+                    .filter(|comment| !excluded.contains(&comment.reviewer.username))
+                    .count();
+                received += count as f64 * decay.powi((last - index) as i32);
+            }
+        }
+
+        received
+    }
+
+    /// Returns the usernames of everyone other than the owner and the excluded accounts that
+    /// commented or voted on the change. Submitting the change doesn't count as a review.
+    pub fn reviewers(&self, excluded: &[String], submit_label: &str) -> BTreeSet<String> {
+        let messages = self.comments.iter().map(|comment| &comment.reviewer);
+        let comments = self
+            .patch_sets
+            .iter()
+            .filter_map(|patch| patch.comments.as_ref())
+            .flatten()
+            .map(|comment| &comment.reviewer);
+        let approvals = self
+            .patch_sets
+            .iter()
+            .filter_map(|patch| patch.approvals.as_ref())
+            .flatten()
+            .filter(|approval| approval.review_type != submit_label)
+            .map(|approval| &approval.by);
+
+        messages
+            .chain(comments)
+            .chain(approvals)
+            .filter(|user| user.username != self.owner.username)
+            .filter(|user| !excluded.contains(&user.username))
+            .map(|user| user.username.clone())
+            .collect()
+    }
+
+    /// Returns how often the change was marked as ready for review after being a work in progress,
+    /// detected by the change messages Gerrit writes for it. Changes of Gerrit versions without
+    /// a WIP workflow have none.
+    pub fn wip_ready_transitions(&self) -> u32 {
+        self.comments
+            .iter()
+            .filter(|comment| comment.message.starts_with("Set Ready For Review"))
+            .count() as u32
+    }
+
+    /// Returns the number of inline comment threads on the change and the number of comments in
+    /// them. A comment starts a new thread unless it replies to another comment on the change,
+    /// so without reply linkage every comment is its own thread.
+    pub fn comment_threads(&self) -> (u32, u32) {
+        let comments: Vec<&Comment> = self
+            .patch_sets
+            .iter()
+            .filter_map(|patch| patch.comments.as_ref())
+            .flatten()
+            .collect();
+        let ids: HashSet<&str> = comments
+            .iter()
+            .filter_map(|comment| comment.id.as_deref())
+            .collect();
+
+        let threads = comments
+            .iter()
+            .filter(|comment| {
+                let parent = comment.in_reply_to.as_deref();
+                parent.is_none_or(|parent| !ids.contains(parent))
+            })
+            .count();
+
+        (threads as u32, comments.len() as u32)
+    }
+
+    /// Returns the number of patch sets uploaded after the first one that got a comment or a vote
+    /// from someone other than the owner and the excluded accounts, or None if there was none.
+    pub fn patch_sets_after_first_review(
+        &self,
+        excluded: &[String],
+        submit_label: &str,
+    ) -> Option<u32> {
+        let is_reviewer = |user: &User| {
+            user.username != self.owner.username && !excluded.contains(&user.username)
+        };
+
+        let first_reviewed = self.patch_sets.iter().position(|patch| {
+            let commented = patch
+                .comments
+                .iter()
+                .flatten()
+                .any(|comment| is_reviewer(&comment.reviewer));
+            let voted =
+                patch.approvals.iter().flatten().any(|approval| {
+                    approval.review_type != submit_label && is_reviewer(&approval.by)
+                });
+            commented || voted
+        })?;
+
+        Some((self.patch_sets.len() - first_reviewed - 1) as u32)
+    }
+
+    /// Returns the number of Verified votes the given accounts gave on all patch sets.
+    pub fn verifications(&self, accounts: &[String]) -> u32 {
+        self.patch_sets
+            .iter()
+            .filter_map(|patch| patch.approvals.as_ref())
+            .flatten()
+            .filter(|approval| {
+                approval.review_type == "Verified" && accounts.contains(&approval.by.username)
+            })
+            .count() as u32
+    }
+
+    /// Returns the number of comments the owner made on their own change, e.g. replies to reviewers,
+    /// or the number of patch sets they commented on when counting patch sets.
+    pub fn self_comments(&self, count: SelfCommentCount) -> u32 {
+        let mut self_comments = 0u32;
+
+        for patch in &self.patch_sets {
+            if let Some(comments) = &patch.comments {
+                let own = comments
+                    .iter()
+                    .filter(|comment| comment.reviewer.username == self.owner.username)
+                    .count() as u32;
+                self_comments += match count {
+                    SelfCommentCount::Raw => own,
+                    SelfCommentCount::PatchSets => own.min(1),
+                };
+            }
+        }
+
+        self_comments
+    }
+
+    /// Returns the votes on the last patch set, or on every patch set if configured, for Gerrit
+    /// servers that don't copy votes to new patch sets.
+    fn votes(&self, patch_sets: ApprovalPatchSets) -> impl Iterator<Item = &Approval> {
+        let voted = match patch_sets {
+            ApprovalPatchSets::Last => &self.patch_sets[self.patch_sets.len().saturating_sub(1)..],
+            ApprovalPatchSets::All => &self.patch_sets[..],
+        };
+
+        voted
+            .iter()
+            .filter_map(|patch| patch.approvals.as_ref())
+            .flatten()
+    }
+
+    /// Returns tracked users that approved the change with the labels they approved it with, along
+    /// with the time of their first approving vote. A vote approves if it's one of the given labels
+    /// and its value is at least that label's minimum, or the change's repo minimum if it has one.
+    pub fn approvals(
+        &self,
+        users: &BTreeMap<String, String>,
+        labels: &[ApprovalLabel],
+        repo_min: &BTreeMap<String, i32>,
+        patch_sets: ApprovalPatchSets,
+    ) -> Vec<(String, Vec<String>, i64)> {
+        let mut approval_users: Vec<(String, Vec<String>, i64)> = Vec::new();
+
+        for approval in self.votes(patch_sets) {
+            let label = self.approving_label(approval, labels, repo_min);
+
+            if let (Some(label), true) = (label, users.contains_key(&approval.by.username)) {
+                match approval_users
+                    .iter_mut()
+                    .find(|(user, _, _)| user == &approval.by.username)
+                {
+                    Some((_, user_labels, granted_on)) => {
+                        if !user_labels.contains(&label.name) {
+                            user_labels.push(label.name.clone());
+                        }
+                        *granted_on = (*granted_on).min(approval.granted_on);
+                    }
+                    None => approval_users.push((
+                        approval.by.username.clone(),
+                        vec![label.name.clone()],
+                        approval.granted_on,
+                    )),
+                }
             }
         }
 
         approval_users
     }
 
+    /// Returns the last Code-Review vote every tracked user other than the owner gave on the change,
+    /// as its index in `CODE_REVIEW_VOTES`. Users whose last vote was 0 are left out.
+    pub fn code_review_votes(&self, users: &BTreeMap<String, String>) -> BTreeMap<String, usize> {
+        let mut votes = BTreeMap::new();
+
+        for approval in self.votes(ApprovalPatchSets::All) {
+            if approval.review_type != "Code-Review"
+                || approval.by.username == self.owner.username
+                || !users.contains_key(&approval.by.username)
+            {
+                continue;
+            }
+            let value: i32 = approval.value.parse().unwrap_or_default();
+            match CODE_REVIEW_VOTES.iter().position(|vote| *vote == value) {
+                Some(index) => votes.insert(approval.by.username.clone(), index),
+                None => votes.remove(&approval.by.username),
+            };
+        }
+
+        votes
+    }
+
+    /// Returns tracked users other than the owner that approved the change, with the number of
+    /// seconds between the upload of the patch set they first approved and their approval. Like
+    /// for `approvals` every user is counted once, so votes copied to later patch sets aren't
+    /// counted again. Patch sets without a known upload time are skipped.
+    pub fn approval_delays(
+        &self,
+        users: &BTreeMap<String, String>,
+        labels: &[ApprovalLabel],
+        repo_min: &BTreeMap<String, i32>,
+    ) -> Vec<(String, i64)> {
+        // The first approval of every user, with the upload time of its patch set.
+        let mut first: BTreeMap<&str, (i64, i64)> = BTreeMap::new();
+
+        for patch in &self.patch_sets {
+            let created_on = match patch.created_on {
+                Some(created_on) => created_on,
+                None => continue,
+            };
+
+            for approval in patch.approvals.iter().flatten() {
+                if approval.by.username == self.owner.username
+                    || !users.contains_key(&approval.by.username)
+                    || self.approving_label(approval, labels, repo_min).is_none()
+                {
+                    continue;
+                }
+                let earliest = first
+                    .entry(&approval.by.username)
+                    .or_insert((approval.granted_on, created_on));
+                if approval.granted_on < earliest.0 {
+                    *earliest = (approval.granted_on, created_on);
+                }
+            }
+        }
+
+        first
+            .into_iter()
+            .map(|(user, (granted_on, created_on))| (user.to_string(), granted_on - created_on))
+            .collect()
+    }
+
+    /// Returns the label the vote approves the change with, if any. The minimum of Code-Review can
+    /// be overridden for the repo in `repo_min`, whose keys are glob patterns.
+    fn approving_label<'a>(
+        &self,
+        approval: &Approval,
+        labels: &'a [ApprovalLabel],
+        repo_min: &BTreeMap<String, i32>,
+    ) -> Option<&'a ApprovalLabel> {
+        let value: i32 = approval.value.parse().unwrap_or(i32::MIN);
+        let repo_min = glob::lookup(repo_min, &self.project).copied();
+        labels.iter().find(|label| {
+            let min = match repo_min {
+                Some(min) if label.name == "Code-Review" => min,
+                _ => label.min,
+            };
+            label.name == approval.review_type && value >= min
+        })
+    }
+
+    /// Returns everyone other than the owner that approved the change, tracked user or not.
+    pub fn approvers(
+        &self,
+        labels: &[ApprovalLabel],
+        repo_min: &BTreeMap<String, i32>,
+        patch_sets: ApprovalPatchSets,
+    ) -> BTreeSet<String> {
+        self.votes(patch_sets)
+            .filter(|approval| approval.by.username != self.owner.username)
+            .filter(|approval| self.approving_label(approval, labels, repo_min).is_some())
+            .map(|approval| approval.by.username.clone())
+            .collect()
+    }
+
+    /// Returns whether anyone other than the owner approved the change, tracked user or not.
+    pub fn is_approved_by_others(
+        &self,
+        labels: &[ApprovalLabel],
+        repo_min: &BTreeMap<String, i32>,
+        patch_sets: ApprovalPatchSets,
+    ) -> bool {
+        !self.approvers(labels, repo_min, patch_sets).is_empty()
+    }
+
     pub fn patch_set_count(&self) -> u32 {
         self.patch_sets.len() as u32
     }
 
+    /// Returns the number of patch sets that changed the code, i.e. all but trivial rebases and
+    /// patch sets that only changed the commit message. Patch sets without a kind are counted.
+    pub fn substantive_patch_set_count(&self) -> u32 {
+        const TRIVIAL: &[&str] = &["TRIVIAL_REBASE", "NO_CODE_CHANGE", "NO_CHANGE"];
+
+        self.patch_sets
+            .iter()
+            .filter(|patch| !TRIVIAL.contains(&patch.kind.as_deref().unwrap_or_default()))
+            .count() as u32
+    }
+
+    pub fn commit_message(&self) -> &str {
+        &self.commit_message
+    }
+
+    /// Returns the first line of the commit message.
+    pub fn subject(&self) -> &str {
+        self.commit_message().lines().next().unwrap_or_default()
+    }
+
+    /// Returns the number of lines the last patch set inserted and deleted, if Gerrit reported them.
+    pub fn lines_changed(&self) -> Option<u32> {
+        let patch = self.patch_sets.last()?;
+        let lines = patch.size_insertions?.abs() + patch.size_deletions?.abs();
+        Some(lines as u32)
+    }
+
     pub fn commit_message_words(&self) -> u32 {
         self.commit_message.split_whitespace().count() as u32
     }
+
+    /// Returns the first tracked reviewer (other than the owner) that commented or voted on this
+    /// change, along with the number of seconds that passed since the first patch set was created.
+    pub fn first_response(
+        &self,
+        users: &BTreeMap<String, String>,
+        submit_label: &str,
+    ) -> Option<(String, i64)> {
+        let created_on = self.patch_sets.first()?.created_on?;
+        let mut first: Option<(&User, i64)> = None;
+
+        let comments = self
+            .comments
+            .iter()
+            .filter_map(|comment| comment.timestamp.map(|time| (&comment.reviewer, time)));
+        let approvals = self
+            .patch_sets
+            .iter()
+            .filter_map(|patch| patch.approvals.as_ref())
+            .flatten()
+            .filter(|approval| approval.review_type != submit_label)
+            .map(|approval| (&approval.by, approval.granted_on));
+
+        for (user, time) in comments.chain(approvals) {
+            if user.username == self.owner.username || !users.contains_key(&user.username) {
+                continue;
+            }
+            if first.is_none_or(|(_, first_time)| time < first_time) {
+                first = Some((user, time));
+            }
+        }
+
+        first.map(|(user, time)| (user.username.clone(), time - created_on))
+    }
+
+    /// Returns how many seconds the owner took to upload the next patch set after the first comment
+    /// of a reviewer other than the given accounts on every patch set. Patch sets that got no
+    /// comments, or that the owner never followed up on, are skipped.
+    pub fn author_turnarounds(&self, excluded: &[String]) -> Vec<i64> {
+        let uploads: Vec<i64> = self.upload_times().collect();
+        let comment_times: Vec<i64> = self
+            .comments
+            .iter()
+            .filter(|comment| {
+                comment.reviewer.username != self.owner.username
+                    && !excluded.contains(&comment.reviewer.username)
+            })
+            .filter_map(|comment| comment.timestamp)
+            .collect();
+
+        uploads
+            .windows(2)
+            .filter_map(|uploads| {
+                let (uploaded, next) = (uploads[0], uploads[1]);
+                let first_comment = comment_times
+                    .iter()
+                    .filter(|time| (uploaded..next).contains(*time))
+                    .min()?;
+                Some(next - first_comment)
+            })
+            .collect()
+    }
+
+    /// Returns who uploaded the last patch set, if Gerrit reported it.
+    pub fn uploader(&self) -> Option<&User> {
+        self.patch_sets
+            .last()
+            .and_then(|patch| patch.uploader.as_ref())
+    }
+
+    /// Returns the times patch sets were uploaded, patch sets without a known upload time are left
+    /// out.
+    pub fn upload_times(&self) -> impl Iterator<Item = i64> + '_ {
+        self.patch_sets.iter().filter_map(|patch| patch.created_on)
+    }
+
+    /// Returns every action taken on the change with who took it and when: patch set uploads by the
+    /// owner, comments and votes.
+    pub fn activity(&self) -> Vec<(&str, i64)> {
+        let uploads = self
+            .upload_times()
+            .map(|time| (self.owner.username.as_str(), time));
+        let comments = self.comments.iter().filter_map(|comment| {
+            comment
+                .timestamp
+                .map(|time| (comment.reviewer.username.as_str(), time))
+        });
+        let approvals = self
+            .patch_sets
+            .iter()
+            .filter_map(|patch| patch.approvals.as_ref())
+            .flatten()
+            .map(|approval| (approval.by.username.as_str(), approval.granted_on));
+
+        uploads.chain(comments).chain(approvals).collect()
+    }
+}
+
+/// Returns the largest number of the intervals that overlap at any one time. Intervals that only
+/// touch, one ending when the next starts, don't overlap.
+pub fn peak_overlap(intervals: &[(i64, i64)]) -> u32 {
+    let mut events: Vec<(i64, i32)> = intervals
+        .iter()
+        .flat_map(|&(start, end)| vec![(start, 1), (end, -1)])
+        .collect();
+    // Ends sort before starts at the same time.
+    events.sort_unstable();
+
+    let mut open = 0;
+    let mut peak = 0;
+    for (_, change) in events {
+        open += change;
+        peak = peak.max(open);
+    }
+    peak as u32
+}
+
+/// Returns whether the line is the stats row, that Gerrit prints after all changes of a query.
+pub fn is_stats_row(line: &str) -> bool {
+    #[derive(Deserialize)]
+    struct Row {
+        #[serde(rename = "type")]
+        row_type: Option<String>,
+    }
+
+    match serde_json::from_str::<Row>(line) {
+        Ok(row) => row.row_type.as_deref() == Some("stats"),
+        Err(_) => false,
+    }
+}
+
+/// Line of `gerrit query` output that couldn't be parsed as a change.
+enum ParseError {
+    /// The line isn't a JSON object, so the output isn't `--format JSON`, holding its start.
+    NotJson(String),
+    /// The line is a JSON object that isn't a change Gerrit would print, e.g. without `project`.
+    Invalid(String),
+}
+
+/// Parses raw `gerrit query --format JSON` output, one change per line. The stats rows Gerrit
+/// prints after each query are skipped, empty output has no changes. Output that isn't a JSON
+/// object per line, e.g. from `--format TEXT` or pretty-printed JSON, is an error. Objects that
+/// aren't valid changes are skipped with a warning.
+pub fn parse_output(output: &str) -> Vec<Review> {
+    collect_changes(output.lines().map(parse_line))
+}
+
+/// Parses a line of `gerrit query` output, see `parse_output`. Empty lines and stats rows have no
+/// change.
+fn parse_line(line: &str) -> Result<Option<Review>, ParseError> {
+    if line.trim().is_empty() || is_stats_row(line) {
+        return Ok(None);
+    }
+
+    let value = match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(value) if value.is_object() => value,
+        _ => return Err(ParseError::NotJson(line.chars().take(80).collect())),
+    };
+    let number = value.get("number").map(ToString::to_string);
+    match serde_json::from_value(value) {
+        Ok(review) => Ok(Some(review)),
+        Err(err) => Err(ParseError::Invalid(match number {
+            Some(number) => format!("change {}: {}", number, err),
+            None => format!("change without a number: {}", err),
+        })),
+    }
+}
+
+/// Returns the parsed changes, printing a warning for every line that was skipped. A line that
+/// isn't JSON is an error.
+fn collect_changes(lines: impl Iterator<Item = Result<Option<Review>, ParseError>>) -> Vec<Review> {
+    let mut reviews = Vec::new();
+
+    for line in lines {
+        match line {
+            Ok(review) => reviews.extend(review),
+            Err(ParseError::Invalid(warning)) => {
+                eprintln!(
+                    "Skipped a line of Gerrit output, failed to parse {}",
+                    warning
+                );
+            }
+            Err(ParseError::NotJson(start)) => {
+                eprintln!(
+                    "Failed to parse Gerrit output: expected one JSON object per line, did you pass \
+                     --format JSON? The line starts with: {}",
+                    start
+                );
+                crate::lock::exit(1);
+            }
+        }
+    }
+
+    reviews
+}
+
+/// Parses the outputs like `parse_output` with the lines split evenly across the given number of
+/// threads. The changes keep the order of the outputs.
+pub fn parse_outputs(outputs: &[&str], threads: usize) -> Vec<Review> {
+    if threads <= 1 {
+        return outputs
+            .iter()
+            .flat_map(|output| parse_output(output))
+            .collect();
+    }
+
+    let lines: Vec<&str> = outputs.iter().flat_map(|output| output.lines()).collect();
+    if lines.is_empty() {
+        return Vec::new();
+    }
+
+    // Workers only parse, the results are collected in order afterwards, so every warning is
+    // printed once and a line that isn't JSON exits from the main thread.
+    let chunk_size = lines.len().div_ceil(threads);
+    let results = std::thread::scope(|scope| {
+        let parsers: Vec<_> = lines
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(|line| parse_line(line)).collect()))
+            .collect();
+        parsers
+            .into_iter()
+            .flat_map(|parser| -> Vec<_> { parser.join().expect("Failed to join parser thread") })
+            .collect::<Vec<_>>()
+    });
+    collect_changes(results.into_iter())
+}
+
+/// Replaces alias usernames of owners, commenters and approvers with their canonical usernames,
+/// so that all accounts of a user roll up into a single entry. Accounts without a username are
+/// looked up by their lowercase email in the aliases, or identified by the email otherwise.
+pub fn resolve_users(reviews: &mut [Review], aliases: &BTreeMap<String, String>) {
+    let rename = |user: &mut User| {
+        if user.username.is_empty() {
+            if let Some(email) = &user.email {
+                user.username = email.to_lowercase();
+            }
+        }
+        if let Some(username) = aliases.get(&user.username) {
+            user.username = username.clone();
+        }
+    };
+
+    for review in reviews {
+        rename(&mut review.owner);
+        review
+            .comments
+            .iter_mut()
+            .for_each(|comment| rename(&mut comment.reviewer));
+
+        for patch in &mut review.patch_sets {
+            patch.uploader.iter_mut().for_each(rename);
+            patch
+                .approvals
+                .iter_mut()
+                .flatten()
+                .for_each(|approval| rename(&mut approval.by));
+            patch
+                .comments
+                .iter_mut()
+                .flatten()
+                .for_each(|comment| rename(&mut comment.reviewer));
+        }
+    }
+}
+
+/// Removes the change messages and inline comments the predicate holds for from every review.
+pub fn remove_comments<F: Fn(&Comment) -> bool>(reviews: &mut [Review], remove: F) {
+    for review in reviews {
+        review.comments.retain(|comment| !remove(comment));
+        for patch in &mut review.patch_sets {
+            if let Some(comments) = &mut patch.comments {
+                comments.retain(|comment| !remove(comment));
+            }
+        }
+    }
+}
+
+/// Returns the commit shas the change reverts, from the `This reverts commit <sha>.` lines Gerrit
+/// and git write into the commit message of a revert.
+fn reverted_commits(review: &Review) -> Vec<&str> {
+    review
+        .commit_message
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("This reverts commit "))
+        .map(|sha| sha.trim_end_matches('.'))
+        .filter(|sha| sha.len() >= 7 && sha.chars().all(|c| c.is_ascii_hexdigit()))
+        .collect()
+}
+
+/// Marks every review a fetched change reverts, by matching the reverted commit against the
+/// revisions of its patch sets. Reverts of changes that weren't fetched are skipped.
+pub fn set_reverted(reviews: &mut [Review]) {
+    let reverted: HashSet<String> = reviews
+        .iter()
+        .flat_map(reverted_commits)
+        .map(|sha| sha.to_lowercase())
+        .collect();
+    if reverted.is_empty() {
+        return;
+    }
+
+    for review in reviews {
+        review.reverted = review
+            .patch_sets
+            .iter()
+            .filter_map(|patch| patch.revision.as_ref())
+            .any(|revision| {
+                let revision = revision.to_lowercase();
+                reverted
+                    .iter()
+                    .any(|sha| revision.starts_with(sha.as_str()))
+            });
+    }
+}
+
+/// Sets stack depth of every review by following `dependsOn` chains among the given reviews.
+/// Changes that don't depend on any of the given reviews have depth of 1.
+pub fn set_stack_depths(reviews: &mut [Review]) {
+    let index: HashMap<i32, usize> = reviews
+        .iter()
+        .enumerate()
+        .map(|(i, review)| (review.number, i))
+        .collect();
+
+    let parents: Vec<Option<usize>> = reviews
+        .iter()
+        .map(|review| {
+            review
+                .depends_on
+                .iter()
+                .find_map(|dependency| index.get(&dependency.number).cloned())
+        })
+        .collect();
+
+    for i in 0..reviews.len() {
+        let mut depth = 1;
+        let mut parent = parents[i];
+
+        // Depth can't exceed the number of reviews, unless dependencies form a cycle.
+        while let Some(p) = parent {
+            if depth as usize >= reviews.len() {
+                break;
+            }
+            depth += 1;
+            parent = parents[p];
+        }
+
+        reviews[i].stack_depth = depth;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATS_ROW: &str =
+        r#"{"type":"stats","rowCount":2,"runTimeMilliseconds":12,"moreChanges":false}"#;
+
+    fn change_row(number: i32) -> String {
+        format!(
+            r#"{{"project":"repo","branch":"master","id":"I{0}","number":{0},"owner":{{"name":"Jane Doe","username":"jado"}},"commitMessage":"Fix","comments":[],"patchSets":[]}}"#,
+            number
+        )
+    }
+
+    #[test]
+    fn parse_empty_output() {
+        assert!(parse_output("").is_empty());
+        assert!(parse_output("\n  \n").is_empty());
+    }
+
+    #[test]
+    fn parse_stats_row_only() {
+        assert!(parse_output(STATS_ROW).is_empty());
+        assert!(parse_output(&format!("{}\n", STATS_ROW)).is_empty());
+    }
+
+    #[test]
+    fn parse_changes_with_stats_row() {
+        let output = format!("{}\n{}\n{}\n", change_row(1), change_row(2), STATS_ROW);
+        let reviews = parse_output(&output);
+
+        let numbers: Vec<i32> = reviews.iter().map(|review| review.number).collect();
+        assert_eq!(numbers, vec![1, 2]);
+    }
+
+    #[test]
+    fn parse_skips_invalid_changes() {
+        let invalid = r#"{"branch":"master","id":"I3","number":3}"#;
+        let output = format!("{}\n{}\n{}\n", change_row(1), invalid, change_row(2));
+
+        for threads in [1, 2] {
+            let reviews = parse_outputs(&[&output], threads);
+            let numbers: Vec<i32> = reviews.iter().map(|review| review.number).collect();
+            assert_eq!(numbers, vec![1, 2]);
+        }
+    }
+
+    #[test]
+    fn parse_concatenated_outputs() {
+        let output = format!(
+            "{}\n{}\n{}\n{}\n",
+            change_row(1),
+            STATS_ROW,
+            change_row(2),
+            STATS_ROW
+        );
+        assert_eq!(parse_output(&output).len(), 2);
+    }
+
+    fn submitted_at(granted_on: i64) -> Review {
+        let row = format!(
+            r#"{{"project":"repo","branch":"master","id":"I1","number":1,"owner":{{"name":"Jane Doe","username":"jado"}},"commitMessage":"Fix","comments":[],"patchSets":[{{"approvals":[{{"type":"SUBM","value":"1","grantedOn":{},"by":{{"name":"Jane Doe","username":"jado"}}}}]}}]}}"#,
+            granted_on
+        );
+        serde_json::from_str(&row).expect("Failed to parse review")
+    }
+
+    #[test]
+    fn is_within_date_on_boundary_seconds() {
+        let from: Date = "2019-01-01".parse().unwrap();
+        let to: Date = "2019-01-31".parse().unwrap();
+
+        assert!(submitted_at(1_546_300_800).is_within_date(&from, &to, "SUBM"));
+        assert!(submitted_at(1_548_979_199).is_within_date(&from, &to, "SUBM"));
+        assert!(!submitted_at(1_546_300_799).is_within_date(&from, &to, "SUBM"));
+        assert!(!submitted_at(1_548_979_200).is_within_date(&from, &to, "SUBM"));
+    }
+
+    #[test]
+    fn is_within_date_without_submit() {
+        let from: Date = "2019-01-01".parse().unwrap();
+        let to: Date = "2019-01-31".parse().unwrap();
+
+        assert!(!serde_json::from_str::<Review>(&change_row(1))
+            .expect("Failed to parse review")
+            .is_within_date(&from, &to, "SUBM"));
+    }
+
+    #[test]
+    fn comments_on_change_and_context() {
+        let review: Review = serde_json::from_str(
+            r#"{"project":"repo","branch":"master","id":"I1","number":1,"owner":{"name":"Jane Doe","username":"jado"},"commitMessage":"Fix","comments":[],"patchSets":[
+                {"files":[
+                    {"file":"/COMMIT_MSG","type":"ADDED","insertions":8},
+                    {"file":"src/new.rs","type":"ADDED","insertions":10},
+                    {"file":"src/old.rs","type":"MODIFIED","insertions":0,"deletions":-4}
+                ],"comments":[
+                    {"file":"src/new.rs","line":3,"reviewer":{"username":"rev"},"message":"Typo"},
+                    {"file":"src/old.rs","line":7,"reviewer":{"username":"rev"},"message":"Why?"},
+                    {"file":"/COMMIT_MSG","line":1,"reviewer":{"username":"rev"},"message":"Subject"},
+                    {"file":"src/new.rs","line":4,"reviewer":{"username":"jado"},"message":"Done"},
+                    {"reviewer":{"username":"rev"},"message":"Patch set comment"}
+                ]},
+                {"comments":[{"file":"src/new.rs","line":5,"reviewer":{"username":"rev"},"message":"Nit"}]}
+            ]}"#,
+        )
+        .expect("Failed to parse review");
+
+        assert_eq!(review.comments_received_on_change(&[]), (1, 3));
+    }
+
+    #[test]
+    fn repo_approval_min_patterns() {
+        let review: Review = serde_json::from_str(
+            r#"{"project":"platform/docs","branch":"master","id":"I1","number":1,"owner":{"name":"Jane Doe","username":"jado"},"commitMessage":"Fix","comments":[],"patchSets":[{"approvals":[
+                {"type":"Code-Review","value":"1","grantedOn":1,"by":{"username":"rev"}},
+                {"type":"QA","value":"1","grantedOn":1,"by":{"username":"qa"}}
+            ]}]}"#,
+        )
+        .expect("Failed to parse review");
+        let labels = [
+            ApprovalLabel {
+                name: "Code-Review".to_string(),
+                min: 2,
+            },
+            ApprovalLabel {
+                name: "QA".to_string(),
+                min: 2,
+            },
+        ];
+        let approvers = |repo_min: &[(&str, i32)]| {
+            let repo_min = repo_min
+                .iter()
+                .map(|(repo, min)| (repo.to_string(), *min))
+                .collect();
+            let approvers = review.approvers(&labels, &repo_min, ApprovalPatchSets::Last);
+            approvers.into_iter().collect::<Vec<_>>()
+        };
+
+        assert!(approvers(&[]).is_empty());
+        assert_eq!(approvers(&[("platform/*", 1)]), vec!["rev"]);
+        assert_eq!(
+            approvers(&[("platform/*", 2), ("platform/docs", 1)]),
+            vec!["rev"]
+        );
+        assert!(approvers(&[("tools/*", 1)]).is_empty());
+    }
+
+    #[test]
+    fn peak_overlap_counts_open_intervals() {
+        assert_eq!(peak_overlap(&[]), 0);
+        assert_eq!(peak_overlap(&[(10, 20)]), 1);
+        assert_eq!(peak_overlap(&[(10, 30), (20, 40), (25, 26)]), 3);
+        assert_eq!(peak_overlap(&[(10, 20), (30, 40)]), 1);
+    }
+
+    #[test]
+    fn peak_overlap_skips_touching_intervals() {
+        assert_eq!(peak_overlap(&[(10, 20), (20, 30), (30, 40)]), 1);
+        assert_eq!(peak_overlap(&[(10, 10), (10, 20)]), 1);
+    }
+
+    fn stacked(number: i32, parents: &[i32]) -> Review {
+        Review {
+            number,
+            depends_on: parents
+                .iter()
+                .map(|&parent| Dependency { number: parent })
+                .collect(),
+            ..Review::default()
+        }
+    }
+
+    fn stack_depths(mut reviews: Vec<Review>) -> Vec<u32> {
+        set_stack_depths(&mut reviews);
+        reviews.iter().map(|review| review.stack_depth).collect()
+    }
+
+    #[test]
+    fn stack_depths_follow_fetched_parents() {
+        assert_eq!(
+            stack_depths(vec![stacked(1, &[]), stacked(2, &[1]), stacked(3, &[2])]),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            stack_depths(vec![stacked(1, &[]), stacked(2, &[])]),
+            vec![1, 1]
+        );
+        assert_eq!(
+            stack_depths(vec![stacked(2, &[1]), stacked(3, &[9, 2])]),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn stack_depths_stop_at_cycle() {
+        assert_eq!(
+            stack_depths(vec![stacked(1, &[2]), stacked(2, &[1])]),
+            vec![2, 2]
+        );
+        assert_eq!(stack_depths(vec![stacked(1, &[1])]), vec![1]);
+    }
+
+    fn committed(number: i32, revision: &str, commit_message: &str) -> Review {
+        Review {
+            number,
+            commit_message: commit_message.to_string(),
+            patch_sets: vec![PatchSet {
+                revision: Some(revision.to_string()),
+                ..PatchSet::default()
+            }],
+            ..Review::default()
+        }
+    }
+
+    #[test]
+    fn reverted_changes_are_marked() {
+        let mut reviews = vec![
+            committed(1, "abcdef1234567890", "Add feature"),
+            committed(2, "1234567abcdef890", "Fix typo"),
+            committed(
+                3,
+                "fedcba0987654321",
+                "Revert \"Add feature\"\n\nThis reverts commit ABCDEF1234567890.\n",
+            ),
+            committed(
+                4,
+                "0987654fedcba321",
+                "Revert\n\nThis reverts commit 1234567.",
+            ),
+        ];
+        set_reverted(&mut reviews);
+
+        let reverted: Vec<bool> = reviews.iter().map(|review| review.reverted).collect();
+        assert_eq!(reverted, vec![true, true, false, false]);
+    }
+
+    #[test]
+    fn reverts_of_unfetched_changes_are_skipped() {
+        let mut reviews = vec![
+            committed(1, "abcdef1234567890", "Add feature"),
+            committed(2, "fedcba0987654321", "This reverts commit 5555555555."),
+            committed(3, "0987654fedcba321", "This reverts commit abc."),
+        ];
+        set_reverted(&mut reviews);
+        assert!(reviews.iter().all(|review| !review.reverted));
+    }
+
+    #[test]
+    fn approval_delays_count_copied_votes_once() {
+        let review: Review = serde_json::from_str(
+            r#"{"project":"repo","branch":"master","id":"I1","number":1,"owner":{"name":"Jane Doe","username":"jado"},"commitMessage":"Fix","comments":[],"patchSets":[
+                {"createdOn":100,"approvals":[
+                    {"type":"Code-Review","value":"2","grantedOn":400,"by":{"username":"rev"}}
+                ]},
+                {"createdOn":300,"approvals":[
+                    {"type":"Code-Review","value":"2","grantedOn":400,"by":{"username":"rev"}},
+                    {"type":"Code-Review","value":"2","grantedOn":350,"by":{"username":"other"}},
+                    {"type":"Code-Review","value":"2","grantedOn":500,"by":{"username":"jado"}}
+                ]},
+                {"createdOn":600,"approvals":[
+                    {"type":"Code-Review","value":"2","grantedOn":400,"by":{"username":"rev"}},
+                    {"type":"Code-Review","value":"2","grantedOn":350,"by":{"username":"other"}}
+                ]}
+            ]}"#,
+        )
+        .expect("Failed to parse review");
+        let users = ["jado", "rev", "other"]
+            .iter()
+            .map(|user| (user.to_string(), user.to_string()))
+            .collect();
+        let labels = vec![ApprovalLabel {
+            name: "Code-Review".to_string(),
+            min: 2,
+        }];
+
+        assert_eq!(
+            review.approval_delays(&users, &labels, &BTreeMap::new()),
+            vec![("other".to_string(), 50), ("rev".to_string(), 300)]
+        );
+    }
 }
@@ -0,0 +1,151 @@
+use serde_json::Value;
+use std::collections::BTreeSet;
+
+use crate::review;
+
+/// Returns the fields Gerrit documents for an object of the kind in its query output, with the kind
+/// of the objects nested in them. Fields of other kinds, e.g. strings, have an empty kind.
+fn known_fields(kind: &str) -> &'static [(&'static str, &'static str)] {
+    match kind {
+        "change" => &[
+            ("project", ""),
+            ("branch", ""),
+            ("topic", ""),
+            ("id", ""),
+            ("number", ""),
+            ("subject", ""),
+            ("owner", "account"),
+            ("assignee", "account"),
+            ("url", ""),
+            ("commitMessage", ""),
+            ("hashtags", ""),
+            ("createdOn", ""),
+            ("lastUpdated", ""),
+            ("open", ""),
+            ("status", ""),
+            ("private", ""),
+            ("wip", ""),
+            ("comments", "message"),
+            ("trackingIds", ""),
+            ("currentPatchSet", "patchSet"),
+            ("patchSets", "patchSet"),
+            ("dependsOn", "dependency"),
+            ("neededBy", "dependency"),
+            ("submitRecords", ""),
+            ("allReviewers", "account"),
+        ],
+        "account" => &[("name", ""), ("email", ""), ("username", "")],
+        "message" => &[("timestamp", ""), ("reviewer", "account"), ("message", "")],
+        "patchSet" => &[
+            ("number", ""),
+            ("revision", ""),
+            ("parents", ""),
+            ("ref", ""),
+            ("uploader", "account"),
+            ("author", "account"),
+            ("createdOn", ""),
+            ("kind", ""),
+            ("approvals", "approval"),
+            ("comments", "comment"),
+            ("files", "file"),
+            ("sizeInsertions", ""),
+            ("sizeDeletions", ""),
+        ],
+        "approval" => &[
+            ("type", ""),
+            ("description", ""),
+            ("value", ""),
+            ("oldValue", ""),
+            ("grantedOn", ""),
+            ("by", "account"),
+        ],
+        "comment" => &[
+            ("file", ""),
+            ("line", ""),
+            ("reviewer", "account"),
+            ("message", ""),
+            ("id", ""),
+            ("inReplyTo", ""),
+            ("unresolved", ""),
+            ("range", "range"),
+        ],
+        "range" => &[
+            ("startLine", ""),
+            ("startCharacter", ""),
+            ("endLine", ""),
+            ("endCharacter", ""),
+        ],
+        "dependency" => &[
+            ("id", ""),
+            ("number", ""),
+            ("revision", ""),
+            ("ref", ""),
+            ("isCurrentPatchSet", ""),
+        ],
+        "file" => &[
+            ("file", ""),
+            ("fileOld", ""),
+            ("type", ""),
+            ("insertions", ""),
+            ("deletions", ""),
+        ],
+        _ => &[],
+    }
+}
+
+fn collect_unknown(value: &Value, kind: &str, path: &str, unknown: &mut BTreeSet<String>) {
+    match value {
+        Value::Array(values) => {
+            for value in values {
+                collect_unknown(value, kind, path, unknown);
+            }
+        }
+        Value::Object(fields) => {
+            let known = known_fields(kind);
+            for (name, value) in fields {
+                let field_path = match path {
+                    "" => name.to_string(),
+                    path => format!("{}.{}", path, name),
+                };
+                match known.iter().find(|(known, _)| known == name) {
+                    Some((_, "")) => {}
+                    Some((_, nested)) => collect_unknown(value, nested, &field_path, unknown),
+                    None => {
+                        unknown.insert(field_path);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Returns the paths of fields in raw `gerrit query --format JSON` output that aren't known to
+/// be in it, e.g. `patchSets.approvals.newField`. Stats rows and lines that aren't objects are
+/// skipped, they are reported by parsing.
+pub fn unknown_fields(output: &str) -> BTreeSet<String> {
+    let mut unknown = BTreeSet::new();
+
+    for line in output.lines().filter(|line| !review::is_stats_row(line)) {
+        if let Ok(value) = serde_json::from_str::<Value>(line) {
+            collect_unknown(&value, "change", "", &mut unknown);
+        }
+    }
+
+    unknown
+}
+
+/// Exits with an error listing the unknown fields in the output, used with `--strict-parse` to
+/// notice changes of Gerrit's output before they are silently misparsed.
+pub fn check_fields(output: &str, source: &str) {
+    let unknown = unknown_fields(output);
+    if unknown.is_empty() {
+        return;
+    }
+
+    eprintln!("Unknown fields in {}:", source);
+    for field in unknown {
+        eprintln!("  {}", field);
+    }
+    crate::lock::exit(1);
+}
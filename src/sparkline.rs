@@ -0,0 +1,83 @@
+use std::collections::BTreeMap;
+
+use crate::date::{Bucket, Timestamp};
+use crate::review::Review;
+use crate::{output, Config};
+
+const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+const WEEK: i64 = 7 * 24 * 60 * 60;
+
+/// Returns the changes every reported user owns per ISO week of their date range.
+fn weekly_changes(reviews: &[Review], config: &Config) -> BTreeMap<String, Vec<u32>> {
+    let mut changes = BTreeMap::new();
+
+    for (username, (from, to)) in config.user_dates() {
+        if !config.is_reported(&username) {
+            continue;
+        }
+
+        let mut weeks: Vec<String> = Vec::new();
+        let end = to.timestamp("23:59:59");
+        let mut time = from.timestamp("00:00:00");
+        while time <= end + WEEK {
+            let week = Bucket::Week.period(time.min(end));
+            if weeks.last() != Some(&week) {
+                weeks.push(week);
+            }
+            time += WEEK;
+        }
+
+        let mut counts = vec![0; weeks.len()];
+        for review in reviews
+            .iter()
+            .filter(|review| review.owner.username == username)
+        {
+            if !config.is_counted_review(review)
+                || !review.is_within_date(&from, &to, &config.submit_label)
+            {
+                continue;
+            }
+            let week = review
+                .counted_time(&config.submit_label)
+                .map(|time| Bucket::Week.period(time));
+            if let Some(index) = weeks.iter().position(|label| Some(label) == week.as_ref()) {
+                counts[index] += 1;
+            }
+        }
+
+        changes.insert(username, counts);
+    }
+
+    changes
+}
+
+/// Renders the counts as block characters scaled to the largest one.
+fn render(counts: &[u32]) -> String {
+    let max = counts.iter().copied().max().unwrap_or_default().max(1);
+    counts
+        .iter()
+        .map(|count| BLOCKS[(count * (BLOCKS.len() as u32 - 1) / max) as usize])
+        .collect()
+}
+
+/// Prints a sparkline of the changes per week of every reported user.
+pub fn print(reviews: &[Review], config: &Config) {
+    let names = output::display_names(config);
+    let changes = weekly_changes(reviews, config);
+    let width = changes
+        .keys()
+        .map(|user| names[user].chars().count())
+        .max()
+        .unwrap_or_default();
+
+    println!("Changes per week:");
+    for (user, counts) in &changes {
+        println!(
+            "{:width$}  {}  {}",
+            names[user],
+            render(counts),
+            counts.iter().sum::<u32>(),
+            width = width
+        );
+    }
+}